@@ -0,0 +1,80 @@
+//! Compiles Intcode programs to Rust ahead of time with `intcode::transpile`.
+//! The generated code is written to `$OUT_DIR/compiled.rs` and included by
+//! `src/intcode/compiled.rs`.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+// The transpiler and the analyses it builds on, included as siblings so
+// their `super::` imports resolve the same way they do under `intcode`.
+#[allow(dead_code)]
+#[path = "src/intcode/cfg.rs"]
+mod cfg;
+#[allow(dead_code)]
+#[path = "src/intcode/disasm.rs"]
+mod disasm;
+#[path = "src/intcode/examples.rs"]
+mod examples;
+#[allow(dead_code)]
+#[path = "src/intcode/opcode.rs"]
+mod opcode;
+#[path = "src/intcode/transpile.rs"]
+mod transpile;
+
+use opcode::{OpCode, ParamMode};
+
+/// Puzzle inputs to compile, by the name of the static they become.
+const PROGRAMS: &[(&str, &str)] = &[("DAY2", "inputs/day2.txt")];
+
+/// Sources the generated code depends on.
+const SOURCES: &[&str] = &[
+    "src/intcode/cfg.rs",
+    "src/intcode/disasm.rs",
+    "src/intcode/examples.rs",
+    "src/intcode/opcode.rs",
+    "src/intcode/transpile.rs",
+    "inputs/day2-test.txt",
+    "inputs/day9-test.txt",
+];
+
+fn parse(s: &str) -> Vec<i64> {
+    s.trim()
+        .split(',')
+        .map(|word| word.parse().expect("Bad digit"))
+        .collect()
+}
+
+fn main() {
+    let mut out = String::new();
+
+    for (name, path) in PROGRAMS {
+        println!("cargo:rerun-if-changed={}", path);
+        let source = fs::read_to_string(path).unwrap();
+        out.push_str(&transpile::transpile(&parse(&source), name));
+        out.push('\n');
+    }
+
+    let mut examples = Vec::new();
+    for (i, source) in examples::ALL.iter().enumerate() {
+        let name = format!("EXAMPLE_{}", i);
+        out.push_str(&transpile::transpile(&parse(source), &name));
+        out.push('\n');
+        examples.push(format!("&{}", name));
+    }
+    writeln!(
+        out,
+        "pub static EXAMPLES: &[&Compiled] = &[{}];",
+        examples.join(", ")
+    )
+    .unwrap();
+
+    for path in SOURCES {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let dest = Path::new(&env::var("OUT_DIR").unwrap()).join("compiled.rs");
+    fs::write(dest, out).unwrap();
+}
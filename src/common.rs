@@ -1,13 +1,22 @@
-use derive_more::{Add, Constructor};
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
+use std::ops;
+use std::str::FromStr;
+pub use tile_derive::Tile;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Add, Constructor)]
-pub struct Pos {
-    pub x: i64,
-    pub y: i64,
-}
+/// A position or offset in `N` dimensions, with coordinates x, y, z, then
+/// w. Points order as they're read, by the last coordinate first: by row,
+/// then by column in two dimensions.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Point<const N: usize>(pub [i64; N]);
+
+/// A position or offset on a grid. Like text and the Intcode screens, x grows
+/// to the right and y grows downwards, so `UP` is negative y.
+pub type Pos = Point<2>;
+pub type Pos3 = Point<3>;
+pub type Pos4 = Point<4>;
 
 #[derive(Debug, Clone, Copy)]
 pub enum Dir {
@@ -15,100 +24,1359 @@ pub enum Dir {
     Right,
 }
 
-impl Pos {
-    pub fn rotate(&mut self, dir: Dir) {
-        match dir {
-            Dir::Left => {
-                let tmp = self.x;
-                self.x = -self.y;
-                self.y = tmp;
-            }
-            Dir::Right => {
-                let tmp = self.x;
-                self.x = self.y;
-                self.y = -tmp;
-            }
+/// A way to face on a grid, north being up the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Heading {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Heading {
+    /// Clockwise from north.
+    pub const ALL: [Heading; 4] = [Heading::North, Heading::East, Heading::South, Heading::West];
+
+    pub fn turn(self, dir: Dir) -> Heading {
+        let quarters = match dir {
+            Dir::Left => 3,
+            Dir::Right => 1,
+        };
+        Heading::ALL[(self as usize + quarters) % 4]
+    }
+
+    pub fn reverse(self) -> Heading {
+        Heading::ALL[(self as usize + 2) % 4]
+    }
+
+    /// The step one square this way, with y growing as `y_axis` says.
+    /// Adding a heading to a `Pos` steps with y down.
+    pub fn to_delta(self, y_axis: YAxis) -> Pos {
+        let delta = match self {
+            Heading::North => Pos::UP,
+            Heading::East => Pos::RIGHT,
+            Heading::South => Pos::DOWN,
+            Heading::West => Pos::LEFT,
         };
+        match y_axis {
+            YAxis::Down => delta,
+            YAxis::Up => Pos::new(delta.x(), -delta.y()),
+        }
+    }
+
+    /// Reads `U`, `R`, `D` and `L`, or the arrows `^`, `>`, `v` and `<`.
+    pub fn from_char(c: char) -> Option<Heading> {
+        match c {
+            'U' | '^' => Some(Heading::North),
+            'R' | '>' => Some(Heading::East),
+            'D' | 'v' => Some(Heading::South),
+            'L' | '<' => Some(Heading::West),
+            _ => None,
+        }
+    }
+
+    /// The arrow drawn for a robot facing this way.
+    pub fn to_arrow(self) -> char {
+        match self {
+            Heading::North => '^',
+            Heading::East => '>',
+            Heading::South => 'v',
+            Heading::West => '<',
+        }
+    }
+
+    /// Reads the movement commands Intcode droids take: 1 north, 2 south,
+    /// 3 west and 4 east.
+    pub fn from_code(code: i64) -> Option<Heading> {
+        match code {
+            1 => Some(Heading::North),
+            2 => Some(Heading::South),
+            3 => Some(Heading::West),
+            4 => Some(Heading::East),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> i64 {
+        match self {
+            Heading::North => 1,
+            Heading::South => 2,
+            Heading::West => 3,
+            Heading::East => 4,
+        }
+    }
+}
+
+impl ops::Add<Heading> for Pos {
+    type Output = Pos;
+
+    fn add(self, heading: Heading) -> Pos {
+        self + heading.to_delta(YAxis::Down)
+    }
+}
+
+impl ops::AddAssign<Heading> for Pos {
+    fn add_assign(&mut self, heading: Heading) {
+        *self = *self + heading;
+    }
+}
+
+/// Offsets to the four positions sharing an edge, clockwise from up, then
+/// the four sharing only a corner.
+const NEIGHBORS: [Pos; 8] = [
+    Pos::UP,
+    Pos::RIGHT,
+    Pos::DOWN,
+    Pos::LEFT,
+    Pos::new(1, -1),
+    Pos::new(1, 1),
+    Pos::new(-1, 1),
+    Pos::new(-1, -1),
+];
+
+impl<const N: usize> Point<N> {
+    pub const ORIGIN: Point<N> = Point([0; N]);
+
+    /// Steps between the two along the axes.
+    pub fn manhattan(self, other: Point<N>) -> i64 {
+        (self - other).0.iter().map(|c| c.abs()).sum()
+    }
+
+    /// Steps between the two when diagonal steps are allowed too.
+    pub fn chebyshev(self, other: Point<N>) -> i64 {
+        (self - other).0.iter().map(|c| c.abs()).max().unwrap_or(0)
+    }
+
+    pub fn signum(self) -> Point<N> {
+        Point(self.0.map(i64::signum))
+    }
+
+    /// The shortest offset in the same direction with whole coordinates, so
+    /// every grid position along a line of sight is a multiple of it.
+    pub fn reduced(self) -> Point<N> {
+        match self.0.iter().fold(0, |d, c| gcd(d, c.abs())) {
+            0 => self,
+            d => Point(self.0.map(|c| c / d)),
+        }
+    }
+
+    /// The 2N points one step along a single axis.
+    pub fn axis_neighbors(self) -> impl Iterator<Item = Point<N>> {
+        (0..N).flat_map(move |axis| {
+            [-1, 1].iter().map(move |&step| {
+                let mut next = self;
+                next.0[axis] += step;
+                next
+            })
+        })
+    }
+
+    /// The 3^N - 1 points at most one step along every axis.
+    pub fn all_neighbors(self) -> impl Iterator<Item = Point<N>> {
+        (0..3usize.pow(N as u32)).filter_map(move |mut i| {
+            let mut next = self;
+            for c in next.0.iter_mut() {
+                *c += (i % 3) as i64 - 1;
+                i /= 3;
+            }
+            (next != self).then_some(next)
+        })
+    }
+}
+
+impl Pos {
+    pub const UP: Pos = Pos::new(0, -1);
+    pub const DOWN: Pos = Pos::new(0, 1);
+    pub const LEFT: Pos = Pos::new(-1, 0);
+    pub const RIGHT: Pos = Pos::new(1, 0);
+
+    pub const fn new(x: i64, y: i64) -> Pos {
+        Point([x, y])
+    }
+
+    pub fn x(&self) -> i64 {
+        self.0[0]
+    }
+
+    pub fn y(&self) -> i64 {
+        self.0[1]
+    }
+
+    /// The four positions sharing an edge with this one.
+    pub fn neighbors4(self) -> impl Iterator<Item = Pos> {
+        NEIGHBORS[..4].iter().map(move |&d| self + d)
+    }
+
+    /// The eight positions sharing an edge or corner with this one.
+    pub fn neighbors8(self) -> impl Iterator<Item = Pos> {
+        NEIGHBORS.iter().map(move |&d| self + d)
+    }
+}
+
+impl Pos3 {
+    pub const fn new(x: i64, y: i64, z: i64) -> Pos3 {
+        Point([x, y, z])
+    }
+
+    pub fn x(&self) -> i64 {
+        self.0[0]
+    }
+
+    pub fn y(&self) -> i64 {
+        self.0[1]
+    }
+
+    pub fn z(&self) -> i64 {
+        self.0[2]
+    }
+}
+
+impl Pos4 {
+    pub const fn new(x: i64, y: i64, z: i64, w: i64) -> Pos4 {
+        Point([x, y, z, w])
+    }
+
+    pub fn x(&self) -> i64 {
+        self.0[0]
+    }
+
+    pub fn y(&self) -> i64 {
+        self.0[1]
+    }
+
+    pub fn z(&self) -> i64 {
+        self.0[2]
+    }
+
+    pub fn w(&self) -> i64 {
+        self.0[3]
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl<const N: usize> Ord for Point<N> {
+    fn cmp(&self, other: &Point<N>) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl<const N: usize> PartialOrd for Point<N> {
+    fn partial_cmp(&self, other: &Point<N>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<const N: usize> ops::Add for Point<N> {
+    type Output = Point<N>;
+
+    fn add(mut self, other: Point<N>) -> Point<N> {
+        self += other;
+        self
+    }
+}
+
+impl<const N: usize> ops::Sub for Point<N> {
+    type Output = Point<N>;
+
+    fn sub(mut self, other: Point<N>) -> Point<N> {
+        self -= other;
+        self
+    }
+}
+
+impl<const N: usize> ops::AddAssign for Point<N> {
+    fn add_assign(&mut self, other: Point<N>) {
+        self.0.iter_mut().zip(&other.0).for_each(|(c, d)| *c += d);
+    }
+}
+
+impl<const N: usize> ops::SubAssign for Point<N> {
+    fn sub_assign(&mut self, other: Point<N>) {
+        self.0.iter_mut().zip(&other.0).for_each(|(c, d)| *c -= d);
+    }
+}
+
+impl<const N: usize> ops::Neg for Point<N> {
+    type Output = Point<N>;
+
+    fn neg(self) -> Point<N> {
+        Point(self.0.map(|c| -c))
+    }
+}
+
+impl<const N: usize> ops::Mul<i64> for Point<N> {
+    type Output = Point<N>;
+
+    fn mul(self, k: i64) -> Point<N> {
+        Point(self.0.map(|c| c * k))
     }
 }
 
+impl<const N: usize> ops::Index<usize> for Point<N> {
+    type Output = i64;
+
+    fn index(&self, axis: usize) -> &i64 {
+        &self.0[axis]
+    }
+}
+
+impl<const N: usize> ops::IndexMut<usize> for Point<N> {
+    fn index_mut(&mut self, axis: usize) -> &mut i64 {
+        &mut self.0[axis]
+    }
+}
+
+/// A kind of cell drawn as a single character, and read back from it.
+/// Derive it with `#[tile('c')]` on every variant, adding
+/// `color = "name"` to draw one in color.
+pub trait Tile: Sized {
+    fn to_char(&self) -> char;
+
+    fn from_char(c: char) -> Option<Self>;
+
+    fn color(&self) -> Option<TermColor> {
+        None
+    }
+}
+
+/// The eight basic terminal colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TermColor {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+}
+
 #[derive(Debug)]
 pub struct Grid<T> {
-    pub grid: HashMap<Pos, T>,
-    char_map: HashMap<T, char>,
-    default: T
+    grid: HashMap<Pos, T>,
+    default: T,
+    /// Holds every cell put, kept up to date as they are.
+    bounds: Option<Rect>,
+}
+
+/// A rectangle of positions, both corners included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub min: Pos,
+    pub max: Pos,
 }
 
 impl<T> Grid<T>
 where
-    T: Hash + Eq + PartialEq + Debug,
+    T: Hash + Eq + PartialEq + Debug + Tile,
 {
-    pub fn new(default: T, char_map: HashMap<T, char>) -> Grid<T> {
+    pub fn new(default: T) -> Grid<T> {
         Grid {
             grid: HashMap::new(),
-            char_map,
-            default
+            default,
+            bounds: None,
         }
     }
 
     pub fn put(&mut self, r: Pos, v: T) {
         self.grid.insert(r, v);
+        match &mut self.bounds {
+            Some(bounds) => bounds.include(r),
+            None => self.bounds = Some(Rect::at(r)),
+        }
+    }
+
+    /// Takes the cell put at `r` back out. The bounds stay as they were until
+    /// `shrink_to_fit`.
+    pub fn remove(&mut self, r: &Pos) -> Option<T> {
+        self.grid.remove(r)
+    }
+
+    /// Shrinks the bounds to the cells left after removing some.
+    pub fn shrink_to_fit(&mut self) {
+        self.bounds = None;
+        for &pos in self.grid.keys() {
+            match &mut self.bounds {
+                Some(bounds) => bounds.include(pos),
+                None => self.bounds = Some(Rect::at(pos)),
+            }
+        }
+        self.grid.shrink_to_fit();
     }
 
     pub fn get(&self, r: &Pos) -> &T {
         self.grid.get(r).unwrap_or(&self.default)
     }
 
-    pub fn bounds(&self) -> ((i64, i64), (i64, i64)) {
-        let mut max_x = std::i64::MIN;
-        let mut min_x = std::i64::MAX;
-        let mut max_y = std::i64::MIN;
-        let mut min_y = std::i64::MAX;
+    /// The smallest rectangle holding every cell put, or `None` before any
+    /// are. After a `remove` it may be larger until `shrink_to_fit`.
+    pub fn bounds(&self) -> Option<Rect> {
+        self.bounds
+    }
+
+    /// Draws the grid as rows of characters, top row first, from its bounds.
+    /// Cells never put are blank.
+    pub fn to_array(&self, orientation: Orientation) -> Vec<Vec<char>> {
+        let bounds = match self.bounds {
+            Some(bounds) => bounds,
+            None => return Vec::new(),
+        };
+        let (min_x, max_x) = (bounds.min.x(), bounds.max.x());
+        let (min_y, max_y) = (bounds.min.y(), bounds.max.y());
+
+        let mut array_grid = vec![vec![' '; bounds.width()]; bounds.height()];
+
+        for (pos, val) in &self.grid {
+            let i = match orientation.y_axis {
+                YAxis::Down => pos.y() - min_y,
+                YAxis::Up => max_y - pos.y(),
+            } as usize;
+            let j = if orientation.flip_x {
+                max_x - pos.x()
+            } else {
+                pos.x() - min_x
+            } as usize;
+
+            array_grid[i][j] = val.to_char();
+        }
+
+        array_grid
+    }
+
+    /// Parses a map of tiles, one row per line with the first line at
+    /// y = 0. Every character is put, so the bounds are those of the text.
+    pub fn parse(text: &str, default: T) -> Result<Grid<T>, ParseGridError> {
+        let mut grid = Grid::new(default);
+        for (pos, c) in chars(text) {
+            let tile = T::from_char(c).ok_or_else(|| ParseGridError::unknown(pos, c))?;
+            grid.put(pos, tile);
+        }
+        Ok(grid)
+    }
+
+    /// A dense copy of the grid's bounds, with the default wherever no cell
+    /// was put.
+    pub fn to_dense(&self) -> Grid2D<T>
+    where
+        T: Clone,
+    {
+        let bounds = match self.bounds {
+            Some(bounds) => bounds,
+            None => return Grid2D::from_rows(Vec::new()),
+        };
+        let rows = (bounds.min.y()..=bounds.max.y())
+            .map(|y| {
+                (bounds.min.x()..=bounds.max.x())
+                    .map(|x| self.get(&Pos::new(x, y)).clone())
+                    .collect()
+            })
+            .collect();
+        Grid2D::from_rows(rows).with_origin(bounds.min)
+    }
+
+    pub fn print_array(&self, orientation: Orientation) {
+        for row in self.to_array(orientation) {
+            println!("{}", row.into_iter().collect::<String>());
+        }
+    }
+
+    /// As `print_array`, drawing tiles that have a color in it.
+    pub fn print_colored(&self, orientation: Orientation) {
+        let colors: HashMap<char, TermColor> = self
+            .grid
+            .values()
+            .filter_map(|tile| Some((tile.to_char(), tile.color()?)))
+            .collect();
+        for row in self.to_array(orientation) {
+            for c in row {
+                match colors.get(&c) {
+                    Some(&color) => print!("\x1b[{}m{}\x1b[0m", 30 + color as u8, c),
+                    None => print!("{}", c),
+                }
+            }
+            println!();
+        }
+    }
+}
+
+impl Rect {
+    /// The rectangle holding just `pos`.
+    pub fn at(pos: Pos) -> Rect {
+        Rect { min: pos, max: pos }
+    }
+
+    pub fn width(&self) -> usize {
+        (self.max.x() - self.min.x() + 1) as usize
+    }
+
+    pub fn height(&self) -> usize {
+        (self.max.y() - self.min.y() + 1) as usize
+    }
+
+    pub fn contains(&self, pos: &Pos) -> bool {
+        (self.min.x()..=self.max.x()).contains(&pos.x())
+            && (self.min.y()..=self.max.y()).contains(&pos.y())
+    }
+
+    /// Grows the rectangle just enough to hold `pos`.
+    pub fn include(&mut self, pos: Pos) {
+        self.min = Pos::new(self.min.x().min(pos.x()), self.min.y().min(pos.y()));
+        self.max = Pos::new(self.max.x().max(pos.x()), self.max.y().max(pos.y()));
+    }
+
+    /// Every position inside, in reading order.
+    pub fn positions(&self) -> impl Iterator<Item = Pos> {
+        let (min, max) = (self.min, self.max);
+        (min.y()..=max.y()).flat_map(move |y| (min.x()..=max.x()).map(move |x| Pos::new(x, y)))
+    }
+}
+
+/// Which way y grows on screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YAxis {
+    Up,
+    Down,
+}
+
+/// How a grid's coordinates map onto the screen when it's drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Orientation {
+    pub y_axis: YAxis,
+    /// Draw x growing to the left rather than to the right.
+    pub flip_x: bool,
+}
+
+impl Orientation {
+    /// Maths convention, as the painting robot moves.
+    pub const Y_UP: Orientation = Orientation {
+        y_axis: YAxis::Up,
+        flip_x: false,
+    };
+    /// Screen convention, as the arcade cabinet draws.
+    pub const Y_DOWN: Orientation = Orientation {
+        y_axis: YAxis::Down,
+        flip_x: false,
+    };
+
+    pub fn flip_x(self) -> Orientation {
+        Orientation {
+            flip_x: !self.flip_x,
+            ..self
+        }
+    }
+}
+
+/// A grid storing every cell of a rectangle, row by row, for maps of fixed
+/// size. Unlike `Grid` there is no default: positions outside the rectangle
+/// have no cell at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grid2D<T> {
+    /// The position of the top left cell.
+    origin: Pos,
+    width: usize,
+    height: usize,
+    cells: Vec<T>,
+}
+
+/// Read access to the cells of a grid, however they're stored, for
+/// algorithms that work on either kind.
+pub trait Cells<T> {
+    /// The cell at `pos`, or `None` if the grid has none there.
+    fn cell(&self, pos: Pos) -> Option<&T>;
+
+    /// Every position holding a cell.
+    fn positions(&self) -> Box<dyn Iterator<Item = Pos> + '_>;
+}
+
+impl<T: Clone> Grid2D<T> {
+    pub fn new(width: usize, height: usize, fill: T) -> Grid2D<T> {
+        Grid2D {
+            origin: Pos::new(0, 0),
+            width,
+            height,
+            cells: vec![fill; width * height],
+        }
+    }
+
+    /// A sparse copy putting every cell, so it keeps the same bounds.
+    pub fn to_sparse(&self, default: T) -> Grid<T>
+    where
+        T: Hash + Eq + Debug + Tile,
+    {
+        let mut grid = Grid::new(default);
+        for (pos, cell) in self.positions().zip(&self.cells) {
+            grid.put(pos, cell.clone());
+        }
+        grid
+    }
+}
+
+impl<T> Grid2D<T> {
+    /// Builds a grid from its rows, top first, which must all be as long.
+    pub fn from_rows(rows: Vec<Vec<T>>) -> Grid2D<T> {
+        let height = rows.len();
+        let width = rows.first().map_or(0, Vec::len);
+        assert!(
+            rows.iter().all(|row| row.len() == width),
+            "Rows of a Grid2D must all be {} long",
+            width
+        );
+        Grid2D {
+            origin: Pos::new(0, 0),
+            width,
+            height,
+            cells: rows.into_iter().flatten().collect(),
+        }
+    }
+
+    /// Moves the grid so its top left cell is at `origin`.
+    pub fn with_origin(self, origin: Pos) -> Grid2D<T> {
+        Grid2D { origin, ..self }
+    }
+
+    pub fn origin(&self) -> Pos {
+        self.origin
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
 
-        self.grid.keys().for_each(|pos| {
-            if pos.x > max_x {
-                max_x = pos.x;
+    fn index(&self, pos: Pos) -> Option<usize> {
+        let x = pos.x() - self.origin.x();
+        let y = pos.y() - self.origin.y();
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return None;
+        }
+        Some(y as usize * self.width + x as usize)
+    }
+
+    pub fn get(&self, pos: &Pos) -> Option<&T> {
+        self.index(*pos).map(|i| &self.cells[i])
+    }
+
+    pub fn get_mut(&mut self, pos: &Pos) -> Option<&mut T> {
+        self.index(*pos).map(move |i| &mut self.cells[i])
+    }
+
+    /// Sets the cell at `pos`, returning whether it's inside the grid.
+    pub fn put(&mut self, pos: Pos, v: T) -> bool {
+        match self.get_mut(&pos) {
+            Some(cell) => {
+                *cell = v;
+                true
             }
-            if pos.x < min_x {
-                min_x = pos.x;
+            None => false,
+        }
+    }
+
+    /// The rows, top first.
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> + '_ {
+        // `chunks` panics on 0, and a grid with no columns has no cells anyway.
+        self.cells
+            .chunks(self.width.max(1))
+            .chain(std::iter::repeat(&[][..]))
+            .take(self.height)
+    }
+
+    /// The cells of column `x`, counting from the left edge, top first.
+    pub fn column(&self, x: usize) -> impl Iterator<Item = &T> + '_ {
+        let width = self.width;
+        let cells = if x < width { &self.cells[x..] } else { &[] };
+        cells.iter().step_by(width.max(1))
+    }
+
+    /// The columns, leftmost first.
+    pub fn columns(&self) -> impl Iterator<Item = impl Iterator<Item = &T> + '_> + '_ {
+        (0..self.width).map(move |x| self.column(x))
+    }
+}
+
+impl<T> Cells<T> for Grid2D<T> {
+    fn cell(&self, pos: Pos) -> Option<&T> {
+        self.get(&pos)
+    }
+
+    /// In row-major order, matching the storage.
+    fn positions(&self) -> Box<dyn Iterator<Item = Pos> + '_> {
+        let (x, y) = (self.origin.x(), self.origin.y());
+        let width = self.width as i64;
+        Box::new(
+            (0..self.height as i64)
+                .flat_map(move |j| (0..width).map(move |i| Pos::new(x + i, y + j))),
+        )
+    }
+}
+
+/// A sparse grid has a cell at every position inside its bounds, the default
+/// where none was put, and none outside them.
+impl<T> Cells<T> for Grid<T>
+where
+    T: Hash + Eq + PartialEq + Debug + Tile,
+{
+    fn cell(&self, pos: Pos) -> Option<&T> {
+        let bounds = self.bounds?;
+        bounds.contains(&pos).then(|| self.get(&pos))
+    }
+
+    /// Every position inside the bounds, in reading order.
+    fn positions(&self) -> Box<dyn Iterator<Item = Pos> + '_> {
+        Box::new(self.bounds.into_iter().flat_map(|bounds| bounds.positions()))
+    }
+}
+
+/// A set of positions connected through their edges.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Region {
+    pub cells: HashSet<Pos>,
+}
+
+impl Region {
+    pub fn contains(&self, pos: &Pos) -> bool {
+        self.cells.contains(pos)
+    }
+
+    pub fn area(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// The number of cell edges on the region's border, holes included.
+    pub fn perimeter(&self) -> usize {
+        self.cells
+            .iter()
+            .flat_map(|pos| pos.neighbors4())
+            .filter(|pos| !self.contains(pos))
+            .count()
+    }
+}
+
+/// Every position reachable from `start` by steps to edge neighbors whose
+/// cells are `passable`, `start` included if its own is. On a sparse grid the
+/// fill stays inside the bounds, even when the default is passable.
+pub fn flood_fill<T, G, F>(grid: &G, start: Pos, passable: F) -> Region
+where
+    G: Cells<T>,
+    F: Fn(&T) -> bool,
+{
+    let open = |pos: Pos| grid.cell(pos).is_some_and(&passable);
+    let mut region = Region::default();
+    if !open(start) {
+        return region;
+    }
+
+    let mut frontier = vec![start];
+    region.cells.insert(start);
+    while let Some(pos) = frontier.pop() {
+        for next in pos.neighbors4() {
+            if open(next) && region.cells.insert(next) {
+                frontier.push(next);
             }
-            if pos.y > max_y {
-                max_y = pos.y;
+        }
+    }
+    region
+}
+
+/// The regions of passable cells, each as filled from the first of its cells
+/// in reading order, and listed in that order too.
+pub fn components<T, G, F>(grid: &G, passable: F) -> Vec<Region>
+where
+    G: Cells<T>,
+    F: Fn(&T) -> bool,
+{
+    let mut positions: Vec<Pos> = grid.positions().collect();
+    positions.sort();
+
+    let mut regions: Vec<Region> = Vec::new();
+    let mut seen = HashSet::new();
+    for pos in positions {
+        if seen.contains(&pos) {
+            continue;
+        }
+        let region = flood_fill(grid, pos, &passable);
+        if region.area() > 0 {
+            seen.extend(region.cells.iter().copied());
+            regions.push(region);
+        }
+    }
+    regions
+}
+
+/// Where a map read from text went wrong, counting lines and columns from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseGridError {
+    UnknownChar {
+        line: usize,
+        column: usize,
+        c: char,
+    },
+    /// A row of a dense map not as long as the first.
+    Ragged {
+        line: usize,
+        len: usize,
+        width: usize,
+    },
+}
+
+/// The positions in a map of the characters picked out as markers.
+pub type Markers = HashMap<char, Vec<Pos>>;
+
+/// Each character of `text` with its position, x along the line and y down
+/// the lines.
+fn chars(text: &str) -> impl Iterator<Item = (Pos, char)> + '_ {
+    text.lines().enumerate().flat_map(|(y, line)| {
+        line.chars()
+            .enumerate()
+            .map(move |(x, c)| (Pos::new(x as i64, y as i64), c))
+    })
+}
+
+impl<T> Grid2D<T> {
+    /// Parses a map with one row per line, turning characters into cells with
+    /// `tile`. Every line must be as long as the first.
+    pub fn parse<F>(text: &str, tile: F) -> Result<Grid2D<T>, ParseGridError>
+    where
+        F: Fn(char) -> Option<T>,
+    {
+        let mut rows: Vec<Vec<T>> = Vec::new();
+        for (pos, c) in chars(text) {
+            if pos.x() == 0 {
+                rows.push(Vec::new());
             }
-            if pos.y < min_y {
-                min_y = pos.y
+            let cell = tile(c).ok_or_else(|| ParseGridError::unknown(pos, c))?;
+            rows.last_mut().unwrap().push(cell);
+        }
+
+        let width = rows.first().map_or(0, Vec::len);
+        // Blank lines have no characters to start a row with.
+        let lines = text.lines().map(|line| line.chars().count());
+        if let Some((y, len)) = lines.enumerate().find(|&(_, len)| len != width) {
+            return Err(ParseGridError::Ragged {
+                line: y + 1,
+                len,
+                width,
+            });
+        }
+        Ok(Grid2D::from_rows(rows))
+    }
+
+    /// As `parse`, but the characters in `markers` stand for `under`, and
+    /// where each one was is recorded: the start, keys and so on drawn over
+    /// open ground.
+    pub fn parse_marked<F>(
+        text: &str,
+        markers: &str,
+        under: T,
+        tile: F,
+    ) -> Result<(Grid2D<T>, Markers), ParseGridError>
+    where
+        T: Clone,
+        F: Fn(char) -> Option<T>,
+    {
+        let grid = Grid2D::parse(text, |c| {
+            if markers.contains(c) {
+                Some(under.clone())
+            } else {
+                tile(c)
             }
-        });
+        })?;
 
-        ((min_x, max_x), (min_y, max_y))
+        let mut found = Markers::new();
+        for (pos, c) in chars(text).filter(|&(_, c)| markers.contains(c)) {
+            found.entry(c).or_default().push(pos);
+        }
+        Ok((grid, found))
     }
+}
 
-    pub fn to_array(&self) -> Vec<Vec<char>> {
-        let ((min_x, max_x), (min_y, max_y)) = self.bounds();
-        let x_size = (max_x.abs() + min_x.abs() + 1) as usize;
-        let y_size = (max_y.abs() + min_y.abs() + 1) as usize;
+impl<T: Tile> FromStr for Grid2D<T> {
+    type Err = ParseGridError;
 
-        let mut array_grid = vec![vec![' '; x_size]; y_size];
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Grid2D::parse(s, T::from_char)
+    }
+}
 
-        for (pos, val) in &self.grid {
-            let i = (pos.y + min_y.abs()) as usize;
-            let j = (pos.x - min_x.abs()) as usize;
+impl ParseGridError {
+    fn unknown(pos: Pos, c: char) -> ParseGridError {
+        ParseGridError::UnknownChar {
+            line: pos.y() as usize + 1,
+            column: pos.x() as usize + 1,
+            c,
+        }
+    }
+}
+
+impl fmt::Display for ParseGridError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseGridError::UnknownChar { line, column, c } => {
+                write!(
+                    f,
+                    "Unknown tile {:?} at line {}, column {}",
+                    c, line, column
+                )
+            }
+            ParseGridError::Ragged { line, len, width } => write!(
+                f,
+                "Line {} is {} long, but the map is {} wide",
+                line, len, width
+            ),
+        }
+    }
+}
 
-            let c = self.char_map.get(val).expect("No char type for value");
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            array_grid[i][j] = *c;
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Tile)]
+    enum Square {
+        #[tile('.')]
+        Open,
+        #[tile('#', color = "blue")]
+        Wall,
+        #[tile(' ')]
+        Unknown,
+    }
+
+    fn grid(cells: &[(i64, i64)]) -> Grid<Square> {
+        let mut grid = Grid::new(Square::Open);
+        for &(x, y) in cells {
+            grid.put(Pos::new(x, y), Square::Wall);
+        }
+        // Mark the corner nearest the origin so orientation shows.
+        if let Some(&(x, y)) = cells.first() {
+            grid.put(Pos::new(x, y), Square::Open);
         }
+        grid
+    }
 
-        array_grid
+    fn render(grid: &Grid<Square>, orientation: Orientation) -> Vec<String> {
+        grid.to_array(orientation)
+            .into_iter()
+            .map(|row| row.into_iter().collect())
+            .collect()
+    }
+
+    // An L with its corner at the first cell.
+    const L: [(i64, i64); 4] = [(0, 0), (1, 0), (2, 0), (0, 1)];
+
+    fn shifted(dx: i64, dy: i64) -> Vec<(i64, i64)> {
+        L.iter().map(|&(x, y)| (x + dx, y + dy)).collect()
+    }
+
+    #[test]
+    fn renders_any_bounds_the_same() {
+        for (dx, dy) in [(0, 0), (5, 7), (-9, -4), (-1, 3), (2, -1)] {
+            let grid = grid(&shifted(dx, dy));
+            assert_eq!(render(&grid, Orientation::Y_DOWN), [".##", "#  "]);
+        }
+    }
+
+    #[test]
+    fn renders_y_up() {
+        let grid = grid(&shifted(-3, -8));
+        assert_eq!(render(&grid, Orientation::Y_UP), ["#  ", ".##"]);
+    }
+
+    #[test]
+    fn renders_x_flipped() {
+        let grid = grid(&shifted(4, 1));
+        assert_eq!(render(&grid, Orientation::Y_DOWN.flip_x()), ["##.", "  #"]);
+        assert_eq!(render(&grid, Orientation::Y_UP.flip_x()), ["  #", "##."]);
     }
 
-    pub fn print_array(&self) {
-        let array = self.to_array();
-        for i in 0..array.len() {
-            for j in 0..array[i].len()  {
-                print!("{}", array[i as usize][j as usize]);
+    #[test]
+    fn renders_empty_grid() {
+        let grid = grid(&[]);
+        assert_eq!(grid.to_array(Orientation::Y_UP), Vec::<Vec<char>>::new());
+    }
+
+    fn dense() -> Grid2D<u8> {
+        Grid2D::from_rows(vec![vec![1, 2, 3], vec![4, 5, 6]])
+    }
+
+    #[test]
+    fn tracks_bounds_as_cells_are_put() {
+        let mut grid = grid(&[]);
+        assert_eq!(grid.bounds(), None);
+        grid.put(Pos::new(2, -1), Square::Wall);
+        assert_eq!(grid.bounds(), Some(Rect::at(Pos::new(2, -1))));
+        grid.put(Pos::new(-3, 4), Square::Wall);
+        grid.put(Pos::new(0, 0), Square::Open);
+
+        let bounds = grid.bounds().unwrap();
+        assert_eq!((bounds.min, bounds.max), (Pos::new(-3, -1), Pos::new(2, 4)));
+        assert_eq!((bounds.width(), bounds.height()), (6, 6));
+        assert!(bounds.contains(&Pos::new(-3, 4)));
+        assert!(!bounds.contains(&Pos::new(3, 0)));
+        assert_eq!(bounds.positions().count(), 36);
+        assert_eq!(bounds.positions().next(), Some(bounds.min));
+        assert!(bounds
+            .positions()
+            .zip(bounds.positions().skip(1))
+            .all(|(a, b)| a < b));
+    }
+
+    #[test]
+    fn shrinks_bounds_after_removing() {
+        let mut grid = grid(&shifted(0, 0));
+        assert_eq!(grid.remove(&Pos::new(2, 0)), Some(Square::Wall));
+        assert_eq!(grid.remove(&Pos::new(2, 0)), None);
+        assert_eq!(grid.bounds().unwrap().width(), 3);
+        assert_eq!(render(&grid, Orientation::Y_DOWN), [".# ", "#  "]);
+
+        grid.shrink_to_fit();
+        assert_eq!(grid.bounds().unwrap().width(), 2);
+        assert_eq!(render(&grid, Orientation::Y_DOWN), [".#", "# "]);
+
+        for pos in shifted(0, 0) {
+            grid.remove(&Pos::new(pos.0, pos.1));
+        }
+        grid.shrink_to_fit();
+        assert_eq!(grid.bounds(), None);
+    }
+
+    #[test]
+    fn dense_get_is_bounds_checked() {
+        let mut grid = dense().with_origin(Pos::new(-1, 3));
+        assert_eq!(grid.get(&Pos::new(-1, 3)), Some(&1));
+        assert_eq!(grid.get(&Pos::new(1, 4)), Some(&6));
+        assert_eq!(grid.get(&Pos::new(2, 4)), None);
+        assert_eq!(grid.get(&Pos::new(0, 2)), None);
+
+        *grid.get_mut(&Pos::new(0, 4)).unwrap() = 9;
+        assert!(!grid.put(Pos::new(-2, 3), 7));
+        assert_eq!(
+            grid.rows().collect::<Vec<_>>(),
+            [&[1, 2, 3][..], &[4, 9, 6]]
+        );
+    }
+
+    #[test]
+    fn dense_rows_and_columns() {
+        let grid = dense();
+        assert_eq!(
+            grid.rows().collect::<Vec<_>>(),
+            [&[1, 2, 3][..], &[4, 5, 6]]
+        );
+        let columns: Vec<Vec<u8>> = grid.columns().map(|c| c.copied().collect()).collect();
+        assert_eq!(columns, [[1, 4], [2, 5], [3, 6]]);
+        assert_eq!(grid.column(3).count(), 0);
+
+        let empty = Grid2D::<u8>::new(0, 2, 0);
+        assert_eq!(empty.rows().count(), 2);
+        assert_eq!(empty.columns().count(), 0);
+    }
+
+    #[test]
+    fn converts_between_sparse_and_dense() {
+        let sparse = grid(&shifted(-2, 5));
+        let dense = sparse.to_dense();
+        assert_eq!(dense.origin(), Pos::new(-2, 5));
+        assert_eq!((dense.width(), dense.height()), (3, 2));
+        assert_eq!(dense.get(&Pos::new(-1, 6)), Some(&Square::Open));
+
+        let round_trip = dense.to_sparse(Square::Open);
+        assert_eq!(round_trip.to_dense(), dense);
+        assert_eq!(render(&round_trip, Orientation::Y_DOWN), [".##", "#.."]);
+        assert_eq!(grid(&[]).to_dense(), Grid2D::from_rows(Vec::new()));
+    }
+
+    #[test]
+    fn derives_tile() {
+        assert_eq!(Square::Wall.to_char(), '#');
+        assert_eq!(Square::from_char(' '), Some(Square::Unknown));
+        assert_eq!(Square::from_char('x'), None);
+        assert_eq!(Square::Wall.color(), Some(TermColor::Blue));
+        assert_eq!(Square::Open.color(), None);
+    }
+
+    #[test]
+    fn parses_dense_map() {
+        let grid: Grid2D<Square> = "#.#\n..#\n".parse().unwrap();
+        assert_eq!((grid.width(), grid.height()), (3, 2));
+        assert_eq!(grid.get(&Pos::new(1, 0)), Some(&Square::Open));
+        assert_eq!(grid.get(&Pos::new(2, 1)), Some(&Square::Wall));
+    }
+
+    #[test]
+    fn records_markers() {
+        let map = "#####\n#@.a#\n#b.a#\n#####";
+        let (grid, markers) =
+            Grid2D::parse_marked(map, "@ab", Square::Open, Square::from_char).unwrap();
+        assert_eq!(grid.get(&Pos::new(1, 1)), Some(&Square::Open));
+        assert_eq!(markers[&'@'], [Pos::new(1, 1)]);
+        assert_eq!(markers[&'a'], [Pos::new(3, 1), Pos::new(3, 2)]);
+        assert_eq!(markers[&'b'], [Pos::new(1, 2)]);
+    }
+
+    #[test]
+    fn reports_where_parsing_failed() {
+        let unknown = "#.#\n.?#".parse::<Grid2D<Square>>().unwrap_err();
+        assert_eq!(
+            unknown,
+            ParseGridError::UnknownChar {
+                line: 2,
+                column: 2,
+                c: '?'
+            }
+        );
+        assert_eq!(unknown.to_string(), "Unknown tile '?' at line 2, column 2");
+
+        let ragged = "#.#\n\n.#".parse::<Grid2D<Square>>().unwrap_err();
+        assert_eq!(
+            ragged,
+            ParseGridError::Ragged {
+                line: 2,
+                len: 0,
+                width: 3
+            }
+        );
+    }
+
+    #[test]
+    fn parses_sparse_map() {
+        let text = ".##\n#  \n";
+        let grid = Grid::parse(text, Square::Unknown).unwrap();
+        assert_eq!(grid.get(&Pos::new(0, 1)), &Square::Wall);
+        assert_eq!(
+            grid.bounds(),
+            Some(Rect {
+                min: Pos::ORIGIN,
+                max: Pos::new(2, 1)
+            })
+        );
+        let drawn: Vec<String> = grid
+            .to_array(Orientation::Y_DOWN)
+            .into_iter()
+            .map(|row| row.into_iter().collect())
+            .collect();
+        assert_eq!(drawn.join("\n") + "\n", text);
+
+        assert_eq!(
+            Grid::parse("##\n#?", Square::Unknown).unwrap_err(),
+            ParseGridError::UnknownChar {
+                line: 2,
+                column: 2,
+                c: '?'
+            }
+        );
+    }
+
+    #[test]
+    fn does_vector_arithmetic() {
+        let mut pos = Pos::new(3, -2);
+        assert_eq!(pos - Pos::new(1, 1), Pos::new(2, -3));
+        assert_eq!(-pos, Pos::new(-3, 2));
+        assert_eq!(pos * 3, Pos::new(9, -6));
+        assert_eq!(Pos::RIGHT * 2 + Pos::UP, Pos::new(2, -1));
+        pos += Pos::DOWN;
+        assert_eq!(pos, Pos::new(3, -1));
+        pos -= Pos::LEFT;
+        assert_eq!(pos, Pos::new(4, -1));
+    }
+
+    #[test]
+    fn measures_distances() {
+        let (a, b) = (Pos::new(1, -2), Pos::new(-3, 5));
+        assert_eq!(a.manhattan(b), 11);
+        assert_eq!(a.chebyshev(b), 7);
+        assert_eq!(a.manhattan(a), 0);
+        assert_eq!((b - a).signum(), Pos::new(-1, 1));
+        assert_eq!(Pos::new(6, -4).reduced(), Pos::new(3, -2));
+        assert_eq!(Pos::new(0, 7).reduced(), Pos::DOWN);
+        assert_eq!(Pos::ORIGIN.reduced(), Pos::ORIGIN);
+    }
+
+    #[test]
+    fn orders_in_reading_order() {
+        let mut positions = vec![Pos::new(2, 1), Pos::new(0, 1), Pos::new(5, 0)];
+        positions.sort();
+        assert_eq!(positions, [Pos::new(5, 0), Pos::new(0, 1), Pos::new(2, 1)]);
+    }
+
+    #[test]
+    fn turns_headings() {
+        assert_eq!(Heading::North.turn(Dir::Left), Heading::West);
+        assert_eq!(Heading::West.turn(Dir::Right), Heading::North);
+        assert_eq!(Heading::East.reverse(), Heading::West);
+        for heading in Heading::ALL.iter().copied() {
+            let back = heading.turn(Dir::Right).turn(Dir::Right);
+            assert_eq!(back, heading.reverse());
+            assert_eq!(heading.to_delta(YAxis::Down).neighbors4().count(), 4);
+        }
+        // Turning right goes clockwise on screen.
+        let steps: Vec<Pos> = Heading::ALL
+            .iter()
+            .map(|heading| heading.turn(Dir::Right).to_delta(YAxis::Down))
+            .collect();
+        assert_eq!(steps, [Pos::RIGHT, Pos::DOWN, Pos::LEFT, Pos::UP]);
+    }
+
+    #[test]
+    fn converts_headings() {
+        assert_eq!(Heading::North.to_delta(YAxis::Down), Pos::UP);
+        assert_eq!(Heading::North.to_delta(YAxis::Up), Pos::new(0, 1));
+        assert_eq!(Heading::East.to_delta(YAxis::Up), Pos::RIGHT);
+
+        let mut pos = Pos::ORIGIN + Heading::East;
+        pos += Heading::South;
+        assert_eq!(pos, Pos::new(1, 1));
+
+        for heading in Heading::ALL.iter().copied() {
+            assert_eq!(Heading::from_char(heading.to_arrow()), Some(heading));
+            assert_eq!(Heading::from_code(heading.code()), Some(heading));
+        }
+        assert_eq!(Heading::from_char('L'), Some(Heading::West));
+        assert_eq!(Heading::from_char('x'), None);
+        assert_eq!(Heading::from_code(0), None);
+    }
+
+    #[test]
+    fn works_in_more_dimensions() {
+        let a = Pos3::new(1, -2, 3);
+        let b = Pos3::new(-1, 0, 7);
+        assert_eq!(a + b, Pos3::new(0, -2, 10));
+        assert_eq!((b - a) * 2, Pos3::new(-4, 4, 8));
+        assert_eq!(a.manhattan(b), 8);
+        assert_eq!(a.chebyshev(b), 4);
+        assert_eq!(Pos3::new(4, 0, -6).reduced(), Pos3::new(2, 0, -3));
+        assert_eq!((a.x(), a.y(), a.z()), (1, -2, 3));
+        assert!(Pos3::new(9, 9, 2) < a);
+
+        assert_eq!(a.axis_neighbors().count(), 6);
+        assert_eq!(a.all_neighbors().collect::<HashSet<_>>().len(), 26);
+        assert!(a.axis_neighbors().all(|n| n.manhattan(a) == 1));
+        assert_eq!(Pos4::ORIGIN.all_neighbors().count(), 80);
+        assert_eq!(Pos4::new(0, 0, 0, 5).w(), 5);
+
+        let pos = Pos::new(3, 4);
+        let all: HashSet<Pos> = pos.all_neighbors().collect();
+        assert_eq!(all, pos.neighbors8().collect());
+        let axes: HashSet<Pos> = pos.axis_neighbors().collect();
+        assert_eq!(axes, pos.neighbors4().collect());
+    }
+
+    #[test]
+    fn simulates_moons() {
+        // Each pair of moons pulls the other one step closer along every
+        // axis, as in the 2019 n-body puzzle's first example.
+        let mut moons = vec![
+            (Pos3::new(-1, 0, 2), Pos3::ORIGIN),
+            (Pos3::new(2, -10, -7), Pos3::ORIGIN),
+            (Pos3::new(4, -8, 8), Pos3::ORIGIN),
+            (Pos3::new(3, 5, -1), Pos3::ORIGIN),
+        ];
+        for _ in 0..10 {
+            let positions: Vec<Pos3> = moons.iter().map(|&(pos, _)| pos).collect();
+            for (pos, vel) in &mut moons {
+                for &other in &positions {
+                    *vel += (other - *pos).signum();
+                }
+                *pos += *vel;
             }
-            println!();
         }
+        let energy: i64 = moons
+            .iter()
+            .map(|(pos, vel)| pos.manhattan(Pos3::ORIGIN) * vel.manhattan(Pos3::ORIGIN))
+            .sum();
+        assert_eq!(energy, 179);
+    }
+
+    #[test]
+    fn finds_neighbors() {
+        let pos = Pos::new(3, -2);
+        let edges: HashSet<Pos> = pos.neighbors4().collect();
+        let all: HashSet<Pos> = pos.neighbors8().collect();
+        assert_eq!(edges.len(), 4);
+        assert_eq!(all.len(), 8);
+        assert!(edges.is_subset(&all));
+        assert!(edges.contains(&Pos::new(3, -3)));
+        assert!(!edges.contains(&Pos::new(4, -1)));
+        assert!(all.contains(&Pos::new(4, -1)));
+        assert!(!all.contains(&pos));
+    }
+
+    const ROOMS: &str = "\
+#######
+#..#..#
+#..#.##
+####..#
+#.#####
+#######";
+
+    #[test]
+    fn fills_from_start() {
+        let grid: Grid2D<Square> = ROOMS.parse().unwrap();
+        let open = |s: &Square| *s == Square::Open;
+        let region = flood_fill(&grid, Pos::new(4, 1), open);
+        assert_eq!(region.area(), 5);
+        assert!(region.contains(&Pos::new(5, 3)));
+        assert!(!region.contains(&Pos::new(1, 1)));
+        assert_eq!(flood_fill(&grid, Pos::new(0, 0), open), Region::default());
+    }
+
+    #[test]
+    fn labels_components() {
+        let grid: Grid2D<Square> = ROOMS.parse().unwrap();
+        let regions = components(&grid, |s| *s == Square::Open);
+        let areas: Vec<usize> = regions.iter().map(Region::area).collect();
+        assert_eq!(areas, [4, 5, 1]);
+        let perimeters: Vec<usize> = regions.iter().map(Region::perimeter).collect();
+        assert_eq!(perimeters, [8, 12, 4]);
+
+        // Put cells only, since the default around them is a wall.
+        let sparse = Grid::parse(ROOMS, Square::Wall).unwrap();
+        assert_eq!(components(&sparse, |s| *s == Square::Open), regions);
+    }
+
+    #[test]
+    fn fills_sparse_grid_within_bounds() {
+        // Open by default, so only the bounds stop the fill around the wall.
+        let mut sparse = Grid::new(Square::Open);
+        sparse.put(Pos::new(0, 0), Square::Open);
+        sparse.put(Pos::new(4, 0), Square::Open);
+        for y in 0..3 {
+            sparse.put(Pos::new(2, y), Square::Wall);
+        }
+        let open = |s: &Square| *s == Square::Open;
+
+        let region = flood_fill(&sparse, Pos::new(0, 2), open);
+        assert_eq!(region.area(), 6);
+        assert!(region.contains(&Pos::new(1, 1)));
+        assert!(!region.contains(&Pos::new(-1, 0)));
+        assert_eq!(flood_fill(&sparse, Pos::new(-1, 0), open), Region::default());
+
+        let areas: Vec<usize> = components(&sparse, open).iter().map(Region::area).collect();
+        assert_eq!(areas, [6, 6]);
+    }
+
+    #[test]
+    fn cells_are_shared_by_both_grids() {
+        fn count<G: Cells<Square>>(grid: &G) -> usize {
+            grid.positions()
+                .filter(|&pos| grid.cell(pos) == Some(&Square::Wall))
+                .count()
+        }
+
+        let sparse = grid(&L);
+        assert_eq!(count(&sparse), 3);
+        assert_eq!(count(&sparse.to_dense()), 3);
+        assert_eq!(sparse.cell(Pos::new(1, 1)), Some(&Square::Open));
+        assert_eq!(sparse.cell(Pos::new(50, 50)), None);
+        assert_eq!(sparse.to_dense().cell(Pos::new(50, 50)), None);
     }
 }
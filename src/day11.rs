@@ -1,10 +1,14 @@
-use super::intcode::IntcodeComputer;
+use super::intcode::{self, patch, IntcodeComputer};
 use super::robot::Robot;
 use std::fs;
 
-pub fn day11() {
+pub fn day11(patches: &[String]) {
     let input = fs::read_to_string("inputs/day11.txt").unwrap();
-    let handle = IntcodeComputer::new(&input, Vec::new());
+    let overrides = patch::overrides("inputs/day11.txt", &intcode::parse(&input), patches)
+        .unwrap_or_else(|e| panic!("{}", e));
+    let handle = IntcodeComputer::new("day11", &input, overrides);
     let mut robot = Robot::new(handle);
-    robot.run();
+    let painted = robot.run();
+    println!("Painted {} tiles", painted);
+    robot.print_grid();
 }
@@ -1,18 +1,24 @@
 use super::common::*;
-use super::intcode::{IntcodeComputer, IntcodeHandle};
+use super::intcode::patch;
+use super::intcode::scan::{Predicate, Scanner};
+use super::intcode::session::{Pause, Session};
+use super::intcode;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
-use std::collections::HashMap;
 use std::fs;
 use std::io::{self, Read};
-use std::sync::mpsc::RecvError;
 
-#[derive(FromPrimitive, Eq, PartialEq, Hash, Debug, Copy, Clone)]
+#[derive(FromPrimitive, Eq, PartialEq, Hash, Debug, Copy, Clone, Tile)]
 enum TileType {
+    #[tile(' ')]
     Empty = 0,
+    #[tile('&')]
     Wall = 1,
+    #[tile('x')]
     Block = 2,
+    #[tile('_')]
     Paddle = 3,
+    #[tile('0')]
     Ball = 4,
 }
 
@@ -22,59 +28,83 @@ enum CpuResult {
     Instr { r: Pos, tile_type: TileType },
 }
 
-pub fn day13() {
+/// Plays the game with `patches` applied, or the stored `free-play` patch if
+/// none are given.
+pub fn day13(patches: &[String]) {
     let input = fs::read_to_string("inputs/day13.txt").unwrap();
-    let handle = IntcodeComputer::new(&input, vec![(0, 2)]);
+    let free_play = ["free-play".to_owned()];
+    let patches = if patches.is_empty() { &free_play } else { patches };
+    let overrides = patch::overrides("inputs/day13.txt", &intcode::parse(&input), patches)
+        .unwrap_or_else(|e| panic!("{}", e));
+    let mut session = Session::new("day13", &input, overrides);
 
-    let mut char_map = HashMap::new();
-    char_map.insert(TileType::Empty, ' ');
-    char_map.insert(TileType::Wall, '&');
-    char_map.insert(TileType::Block, 'x');
-    char_map.insert(TileType::Paddle, '_');
-    char_map.insert(TileType::Ball, '0');
-    let mut grid = Grid::new(TileType::Empty, char_map);
+    let mut grid = Grid::new(TileType::Empty);
     let mut paddle_pos = Pos::new(0, 0);
+    let mut ball_pos = Pos::new(0, 0);
     let mut last_score = 0;
 
-    while let Ok(instr) = read_instr(&handle) {
-        match instr {
-            CpuResult::Score(score) => last_score = score,
-            CpuResult::Instr {
-                r: _r,
-                tile_type: _tile_type,
-            } => {
-                grid.put(_r, _tile_type);
-                if _tile_type == TileType::Paddle {
-                    paddle_pos = _r;
-                } else if _tile_type == TileType::Ball {
-                    if paddle_pos.x < _r.x {
-                        handle.tx_input.send(1).unwrap();
-                    } else if paddle_pos.x > _r.x {
-                        handle.tx_input.send(-1).unwrap();
-                    } else {
-                        handle.tx_input.send(0).unwrap();
+    // Once the game has drawn the screen, look for the words holding what it
+    // drew. Each is narrowed at every pause until a single address is left,
+    // after which it's read from memory rather than from the output.
+    let mut scanners: Vec<(&str, Scanner)> = Vec::new();
+
+    while session.run().unwrap() == Pause::Input {
+        for instr in read_instrs(&session.output()) {
+            match instr {
+                CpuResult::Score(score) => last_score = score,
+                CpuResult::Instr { r, tile_type } => {
+                    grid.put(r, tile_type);
+                    if tile_type == TileType::Paddle {
+                        paddle_pos = r;
+                    } else if tile_type == TileType::Ball {
+                        ball_pos = r;
                     }
                 }
             }
         }
+
+        if scanners.is_empty() {
+            scanners = ["ball x", "ball y", "paddle x", "score"]
+                .iter()
+                .map(|&name| (name, Scanner::new(session.memory())))
+                .collect();
+        }
+        let drawn = [ball_pos.x(), ball_pos.y(), paddle_pos.x(), last_score];
+        for ((_, scanner), &value) in scanners.iter_mut().zip(&drawn) {
+            scanner.narrow(session.memory(), Predicate::Equals(value));
+        }
+
+        let ball_x = scanners[0].1.found().map_or(ball_pos.x(), |a| session.read(a));
+        let paddle_x = scanners[2].1.found().map_or(paddle_pos.x(), |a| session.read(a));
+        session.send((ball_x - paddle_x).signum());
     }
-    
+
+    for instr in read_instrs(&session.output()) {
+        match instr {
+            CpuResult::Score(score) => last_score = score,
+            CpuResult::Instr { r, tile_type } => grid.put(r, tile_type),
+        }
+    }
+    for (name, scanner) in &scanners {
+        match scanner.found() {
+            Some(addr) => println!("The {} is at [{}]", name, addr),
+            None => println!("The {} is one of {:?}", name, scanner.candidates()),
+        }
+    }
+    grid.print_array(Orientation::Y_DOWN);
     println!("The final score is {}", last_score);
 }
 
-fn read_instr(handle: &IntcodeHandle) -> Result<CpuResult, RecvError> {
-    let x = handle.rx_output.recv()?;
-    let y = handle.rx_output.recv()?;
-    let third = handle.rx_output.recv()?;
-
-    if x == -1 && y == 0 {
-        Ok(CpuResult::Score(third))
-    } else {
-        let tile_type = FromPrimitive::from_i64(third).unwrap();
-
-        Ok(CpuResult::Instr {
-            r: Pos::new(x, y),
-            tile_type,
+fn read_instrs(output: &[i64]) -> Vec<CpuResult> {
+    output
+        .chunks(3)
+        .map(|chunk| match *chunk {
+            [-1, 0, score] => CpuResult::Score(score),
+            [x, y, tile] => CpuResult::Instr {
+                r: Pos::new(x, y),
+                tile_type: FromPrimitive::from_i64(tile).unwrap(),
+            },
+            _ => panic!("Output ended partway through a tile: {:?}", chunk),
         })
-    }
+        .collect()
 }
@@ -1,32 +1,27 @@
-use super::intcode::{IntcodeComputer, ParamMode};
-use std::fs;
+use super::intcode::compiled::DAY2;
+use super::intcode::patch;
+use super::intcode::search::{Search, Space};
 
-pub fn day2() {
-    let input = fs::read_to_string("inputs/day2.txt").unwrap();
-    let cpu = IntcodeComputer::new(&input);
-    part1(&cpu);
-    part2(&cpu);
+pub fn day2(patches: &[String]) {
+    let overrides = patch::overrides("inputs/day2.txt", DAY2.program, patches)
+        .unwrap_or_else(|e| panic!("{}", e));
+    part1(&overrides);
+    part2(&overrides);
 }
 
-fn part1(cpu: &IntcodeComputer) {
-    let mut cpu = cpu.clone();
-    cpu.write(1, 12);
-    cpu.write(2, 2);
-    cpu.run();
-    println!("Value left in position 0 is {}", cpu.read(0, None));
+fn part1(overrides: &[(usize, i64)]) {
+    let run = DAY2.execute([overrides, &[(1, 12), (2, 2)]].concat(), &[]);
+    println!("Value left in position 0 is {}", run.read(0));
 }
 
-fn part2(cpu: &IntcodeComputer) {
-    for noun in 0..100 {
-        for verb in 0..100 {
-            let mut cpu = cpu.clone();
-            cpu.write(1, noun);
-            cpu.write(2, verb);
-            cpu.run();
-            if cpu.read(0, None) == 19690720 {
-                println!("Values for noun/verb is {}", 100 * noun + verb);
-                return;
-            }
-        }
-    }
+fn part2(overrides: &[(usize, i64)]) {
+    let space = Space::fixed(overrides.to_vec())
+        .product(Space::overrides(1, 0..100))
+        .product(Space::overrides(2, 0..100));
+    let found = Search::compiled(&DAY2, space)
+        .find(|run| run.read(0) == 19690720)
+        .unwrap();
+    let searched = &found.overrides[overrides.len()..];
+    let (noun, verb) = (searched[0].1, searched[1].1);
+    println!("Values for noun/verb is {}", 100 * noun + verb);
 }
@@ -1,78 +1,68 @@
-use super::intcode::IntcodeComputer;
-use permute::permutations_of;
+use super::intcode::search::Space;
+use super::intcode::{self, patch, CancelToken, Halt, IntcodeComputer};
 use std::fs;
 use std::sync::{mpsc, Arc, Mutex};
-use std::thread;
 
-pub fn day7() {
+pub fn day7(patches: &[String]) {
     let input = fs::read_to_string("inputs/day7.txt").unwrap();
-    part1(&input);
-    part2(&input);
+    let overrides = patch::overrides("inputs/day7.txt", &intcode::parse(&input), patches)
+        .unwrap_or_else(|e| panic!("{}", e));
+    part1(&input, &overrides);
+    part2(&input, &overrides);
 }
 
-fn part1(input: &str) {
-    let mut max = 0;
-
-    permutations_of(&vec![0, 1, 2, 3, 4]).for_each(|permutation| {
-        let mut output = 0;
-        permutation.for_each(|&phase| {
-            let (tx_input, rx_input) = mpsc::channel();
-            let (tx_output, rx_output) = mpsc::channel();
-
-            let handle = IntcodeComputer::new(
-                input.to_owned(),
-                Arc::new(Mutex::new(rx_input)),
-                Arc::new(Mutex::new(tx_output)),
-            );
-            vec![phase, output]
-                .into_iter()
-                .for_each(|v| tx_input.send(v).unwrap());
-            handle.join().unwrap();
-            output = rx_output.recv().unwrap();
-        });
-
-        if output > max {
-            max = output;
-        }
-    });
-
+fn part1(input: &str, overrides: &[(usize, i64)]) {
+    let (_, max) = Space::permutations(&[0, 1, 2, 3, 4])
+        .max_by_key(|c| chain(input, overrides, &c.inputs))
+        .unwrap();
     println!("Max output found was {}", max);
 }
 
-fn part2(input: &str) {
-    let mut max = 0;
-    let size = 5usize;
-
-    permutations_of(&(size..size + 5).collect::<Vec<usize>>()).for_each(|permutation| {
-        let (txs, rxs): (Vec<_>, Vec<_>) = (0..size)
-            .map(|_| {
-                let (tx, rx) = mpsc::channel();
-                (Arc::new(Mutex::new(tx)), Arc::new(Mutex::new(rx)))
-            })
-            .unzip();
+fn part2(input: &str, overrides: &[(usize, i64)]) {
+    let (_, max) = Space::permutations(&[5, 6, 7, 8, 9])
+        .max_by_key(|c| feedback_loop(input, overrides, &c.inputs))
+        .unwrap();
+    println!("Max output found was {}", max);
+}
 
-        let handles = permutation
-            .enumerate()
-            .map(|(i, &phase)| {
-                let input = input.to_owned();
+fn chain(input: &str, overrides: &[(usize, i64)], phases: &[i64]) -> i64 {
+    phases.iter().fold(0, |signal, &phase| {
+        IntcodeComputer::execute("day7", input, overrides.to_vec(), &[phase, signal]).output[0]
+    })
+}
 
-                let (tx, rx) = (txs[i].clone(), rxs[(size + i - 1) % size].clone());
-                txs[i].lock().unwrap().send(phase as i64).unwrap();
+fn feedback_loop(input: &str, overrides: &[(usize, i64)], phases: &[i64]) -> i64 {
+    let size = phases.len();
+    let (txs, rxs): (Vec<_>, Vec<_>) = (0..size)
+        .map(|_| {
+            let (tx, rx) = mpsc::channel();
+            (Arc::new(Mutex::new(tx)), Arc::new(Mutex::new(rx)))
+        })
+        .unzip();
 
-                IntcodeComputer::new(input.clone(), rx, tx)
-            })
-            .collect::<Vec<_>>();
+    // Amplifier i reads from the channel amplifier i - 1 writes to.
+    phases.iter().enumerate().for_each(|(i, &phase)| {
+        txs[(size + i - 1) % size].lock().unwrap().send(phase).unwrap();
+    });
+    txs[size - 1].lock().unwrap().send(0).unwrap();
 
-        txs[size - 1].lock().unwrap().send(0).unwrap();
+    // Sharing a token means an amplifier that fails stops the rest, rather
+    // than leaving them waiting on its output.
+    let cancel = CancelToken::default();
+    let handles = (0..size)
+        .map(|i| {
+            let (tx, rx) = (txs[i].clone(), rxs[(size + i - 1) % size].clone());
+            let cancel = cancel.clone();
+            let overrides = overrides.to_vec();
+            let input = input.to_owned();
+            IntcodeComputer::from_with_cancel("day7", input, overrides, rx, tx, cancel)
+        })
+        .collect::<Vec<_>>();
 
-        handles
-            .into_iter()
-            .for_each(|handle| handle.join().unwrap());
+    for handle in handles {
+        assert_eq!(handle.join().unwrap().halt, Halt::Finished);
+    }
 
-        let output = rxs[size - 1].lock().unwrap().recv().unwrap();
-        if output > max {
-            max = output;
-        }
-    });
-    println!("Max output found was {}", max);
+    let last = rxs[size - 1].lock().unwrap();
+    last.recv().unwrap()
 }
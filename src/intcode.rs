@@ -1,37 +1,150 @@
-use phf::phf_map;
+use log::{debug, warn};
 use std::collections::HashMap;
-use std::sync::mpsc::{Receiver, Sender};
+use std::fmt;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::Duration;
+
+pub mod cfg;
+pub mod compiled;
+pub mod decompile;
+pub mod disasm;
+#[cfg(feature = "jit")]
+mod jit;
+pub mod patch;
+pub mod scan;
+pub mod search;
+pub mod session;
+pub mod smc;
+pub mod stack;
+pub mod symbolic;
+pub mod transpile;
+
+mod opcode;
+pub use self::opcode::ParamMode;
+use self::opcode::{OpCode, OPCODE_SIZE};
+use self::smc::{SelfModification, Tracker};
+use self::stack::{Backtrace, CallStack};
+
+#[cfg(test)]
+mod conformance;
+#[cfg(test)]
+mod examples;
+#[cfg(test)]
+mod fuzz;
 
 #[derive(Debug)]
 pub struct IntcodeComputer {
+    /// Numbers VMs in the order they were loaded, for telling apart their
+    /// log events.
+    id: usize,
+    /// What the program was loaded as, such as the puzzle it's the input
+    /// for, shared by every VM running it.
+    name: String,
+    /// Instructions executed so far.
+    instructions: u64,
     memory: HashMap<usize, i64>,
     instr_ptr: usize,
     rel_base: i64,
     input: Arc<Mutex<Receiver<i64>>>,
     output: Arc<Mutex<Sender<i64>>>,
+    backend: Backend,
+    #[cfg(feature = "jit")]
+    jit: Option<Box<jit::Jit>>,
+    /// Only set for `execute_with_smc_tracking`, as it costs a lookup per
+    /// instruction.
+    smc: Option<Tracker>,
+    stack: CallStack,
+    control: Option<Receiver<Control>>,
+    cancel: Option<CancelToken>,
+}
+
+/// How an `IntcodeComputer` executes its program, chosen when it is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Interpreter,
+    /// Compiles basic blocks to native code with Cranelift as they are
+    /// reached.
+    #[cfg(feature = "jit")]
+    Jit,
 }
 
 #[derive(Debug)]
 pub struct IntcodeHandle {
-    pub thread_handle: JoinHandle<()>,
+    pub thread_handle: JoinHandle<VmState>,
     pub tx_input: Sender<i64>,
     pub rx_output: Receiver<i64>,
+    control: Sender<Control>,
+    cancel: CancelToken,
 }
 
-#[derive(Debug, Clone)]
-struct OpCode {
-    code: u32,
-    modes: Vec<ParamMode>,
+/// Tells every VM holding a clone of it to stop, at its next instruction or
+/// while it waits for input. A VM that stops with an error cancels its token
+/// too, so a group of VMs feeding each other can share one and not wait
+/// forever on a member that has died.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Requests to a VM running on its own thread, answered between instructions
+/// and while it waits for input.
+#[derive(Debug)]
+enum Control {
+    Dump(Sender<HashMap<usize, i64>>),
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum ParamMode {
-    Position,
-    Immediate,
-    Relative,
+/// Instructions the interpreter runs between checks for control requests.
+const CONTROL_INTERVAL: u64 = 1 << 12;
+/// How often a VM waiting for input checks for control requests.
+const CONTROL_POLL: Duration = Duration::from_millis(10);
+
+/// Everything left behind by a VM run on its own thread, returned by joining
+/// it.
+#[derive(Debug)]
+pub struct VmState {
+    pub memory: HashMap<usize, i64>,
+    pub instr_ptr: usize,
+    pub rel_base: i64,
+    pub halt: Halt,
+    pub backtrace: Backtrace,
+}
+
+/// Why a VM stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Halt {
+    /// It reached opcode 99.
+    Finished,
+    Error(IntcodeError),
+    /// Its `CancelToken` was cancelled.
+    Cancelled,
+}
+
+/// Everything left behind by a program run with `IntcodeComputer::execute`.
+#[derive(Debug)]
+pub struct RunResult {
+    pub memory: HashMap<usize, i64>,
+    pub output: Vec<i64>,
+    pub result: Result<(), IntcodeError>,
+    /// Writes to instruction words, if run by `execute_with_smc_tracking`.
+    /// Code run by the JIT isn't tracked, only what it hands to the
+    /// interpreter.
+    pub self_modifications: Vec<SelfModification>,
+}
+
+/// Why a program stopped other than by reaching opcode 99. Every variant
+/// records the address of the instruction that was executing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntcodeError {
+    BadInstruction { addr: usize, value: i64 },
+    ImmediateWrite { addr: usize },
+    NegativeAddress { addr: usize, value: i64 },
+    InputClosed { addr: usize },
+    OutputClosed { addr: usize },
+    Overflow { addr: usize },
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -40,222 +153,720 @@ pub enum RWMode {
     Write,
 }
 
+/// Parses a comma separated program into its initial memory.
+pub fn parse(s: &str) -> Vec<i64> {
+    s.trim()
+        .split(",")
+        .map(|substr| substr.parse::<i64>().expect("Bad digit"))
+        .collect()
+}
+
 impl IntcodeComputer {
-    pub fn new(s: &str, overrides: Vec<(usize, i64)>) -> IntcodeHandle {
+    /// Starts `s` on a thread of its own, under `name` in log events.
+    pub fn new(name: &str, s: &str, overrides: Vec<(usize, i64)>) -> IntcodeHandle {
+        IntcodeComputer::new_with_backend(name, s, overrides, Backend::Interpreter)
+    }
+
+    pub fn new_with_backend(
+        name: &str,
+        s: &str,
+        overrides: Vec<(usize, i64)>,
+        backend: Backend,
+    ) -> IntcodeHandle {
         let (tx_input, rx_input) = mpsc::channel();
         let (tx_output, rx_output) = mpsc::channel();
-
-        let thread_handle = IntcodeComputer::from(
-            s.to_owned(),
-            overrides,
-            Arc::new(Mutex::new(rx_input)),
-            Arc::new(Mutex::new(tx_output)),
-        );
+        let (control, rx_control) = mpsc::channel();
+        let cancel = CancelToken::default();
+
+        let (name, s) = (name.to_owned(), s.to_owned());
+        let input = Arc::new(Mutex::new(rx_input));
+        let output = Arc::new(Mutex::new(tx_output));
+        let token = cancel.clone();
+        let thread_handle = IntcodeComputer::spawn(move || {
+            let mut cpu = IntcodeComputer::load(&name, &s, overrides, input, output, backend);
+            cpu.control = Some(rx_control);
+            cpu.cancel = Some(token);
+            cpu
+        });
 
         IntcodeHandle {
             thread_handle,
             tx_input,
             rx_output,
+            control,
+            cancel,
         }
     }
 
     pub fn from(
+        name: &str,
         s: String,
         overrides: Vec<(usize, i64)>,
         input: Arc<Mutex<Receiver<i64>>>,
         output: Arc<Mutex<Sender<i64>>>,
-    ) -> JoinHandle<()> {
+    ) -> JoinHandle<VmState> {
+        let backend = Backend::Interpreter;
+        IntcodeComputer::from_with_backend(name, s, overrides, input, output, backend)
+    }
+
+    pub fn from_with_backend(
+        name: &str,
+        s: String,
+        overrides: Vec<(usize, i64)>,
+        input: Arc<Mutex<Receiver<i64>>>,
+        output: Arc<Mutex<Sender<i64>>>,
+        backend: Backend,
+    ) -> JoinHandle<VmState> {
+        let name = name.to_owned();
+        IntcodeComputer::spawn(move || {
+            IntcodeComputer::load(&name, &s, overrides, input, output, backend)
+        })
+    }
+
+    /// As `from`, stopping once `cancel` is cancelled.
+    pub fn from_with_cancel(
+        name: &str,
+        s: String,
+        overrides: Vec<(usize, i64)>,
+        input: Arc<Mutex<Receiver<i64>>>,
+        output: Arc<Mutex<Sender<i64>>>,
+        cancel: CancelToken,
+    ) -> JoinHandle<VmState> {
+        let name = name.to_owned();
+        IntcodeComputer::spawn(move || {
+            let backend = Backend::Interpreter;
+            let mut cpu = IntcodeComputer::load(&name, &s, overrides, input, output, backend);
+            cpu.cancel = Some(cancel);
+            cpu
+        })
+    }
+
+    /// Runs the VM `load` sets up on a new thread.
+    fn spawn<F>(load: F) -> JoinHandle<VmState>
+    where
+        F: FnOnce() -> IntcodeComputer + Send + 'static,
+    {
         thread::spawn(move || {
-            let memory: HashMap<_, _> = s
-                .trim()
-                .split(",")
-                .map(|substr| substr.parse::<i64>().expect("Bad digit"))
-                .enumerate()
-                .collect();
-            let mut cpu = IntcodeComputer {
-                memory,
-                instr_ptr: 0,
-                rel_base: 0,
-                input,
-                output,
+            let mut cpu = load();
+            let halt = cpu.run();
+            if let Halt::Error(e) = halt {
+                // Nobody may ever look at the state this thread returns, so
+                // failures are always reported.
+                warn!("vm={} program={} {}\n{}", cpu.id, cpu.name, e, cpu.backtrace());
+                if let Some(cancel) = &cpu.cancel {
+                    cancel.cancel();
+                }
+            }
+
+            VmState {
+                backtrace: cpu.backtrace(),
+                memory: cpu.memory,
+                instr_ptr: cpu.instr_ptr,
+                rel_base: cpu.rel_base,
+                halt,
+            }
+        })
+    }
+
+    /// Runs a program to completion on the current thread, with all of its
+    /// input supplied up front.
+    pub fn execute(
+        name: &str,
+        s: &str,
+        overrides: Vec<(usize, i64)>,
+        inputs: &[i64],
+    ) -> RunResult {
+        IntcodeComputer::execute_with_backend(name, s, overrides, inputs, Backend::Interpreter)
+    }
+
+    pub fn execute_with_backend(
+        name: &str,
+        s: &str,
+        overrides: Vec<(usize, i64)>,
+        inputs: &[i64],
+        backend: Backend,
+    ) -> RunResult {
+        IntcodeComputer::execute_tracking(name, s, overrides, inputs, backend, false, None)
+    }
+
+    /// As `execute`, stopping once `cancel` is cancelled.
+    pub fn execute_with_cancel(
+        name: &str,
+        s: &str,
+        overrides: Vec<(usize, i64)>,
+        inputs: &[i64],
+        cancel: CancelToken,
+    ) -> RunResult {
+        let backend = Backend::Interpreter;
+        IntcodeComputer::execute_tracking(name, s, overrides, inputs, backend, false, Some(cancel))
+    }
+
+    /// As `execute`, also recording writes to instruction words in
+    /// `RunResult::self_modifications`.
+    pub fn execute_with_smc_tracking(
+        name: &str,
+        s: &str,
+        overrides: Vec<(usize, i64)>,
+        inputs: &[i64],
+    ) -> RunResult {
+        let backend = Backend::Interpreter;
+        IntcodeComputer::execute_tracking(name, s, overrides, inputs, backend, true, None)
+    }
+
+    fn execute_tracking(
+        name: &str,
+        s: &str,
+        overrides: Vec<(usize, i64)>,
+        inputs: &[i64],
+        backend: Backend,
+        smc: bool,
+        cancel: Option<CancelToken>,
+    ) -> RunResult {
+        let (tx_input, rx_input) = mpsc::channel();
+        let (tx_output, rx_output) = mpsc::channel();
+        inputs.iter().for_each(|&v| tx_input.send(v).unwrap());
+        drop(tx_input);
+
+        let mut cpu = IntcodeComputer::load(
+            name,
+            s,
+            Vec::new(),
+            Arc::new(Mutex::new(rx_input)),
+            Arc::new(Mutex::new(tx_output)),
+            backend,
+        );
+        if smc {
+            cpu.smc = Some(Tracker::new(parse(s)));
+        }
+        cpu.cancel = cancel;
+        overrides.into_iter().for_each(|(i, val)| cpu.write(i, val));
+        let result = match cpu.run() {
+            Halt::Error(e) => Err(e),
+            _ => Ok(()),
+        };
+
+        RunResult {
+            memory: cpu.memory,
+            output: rx_output.try_iter().collect(),
+            result,
+            self_modifications: cpu.smc.map_or_else(Vec::new, Tracker::into_writes),
+        }
+    }
+
+    fn load(
+        name: &str,
+        s: &str,
+        overrides: Vec<(usize, i64)>,
+        input: Arc<Mutex<Receiver<i64>>>,
+        output: Arc<Mutex<Sender<i64>>>,
+        backend: Backend,
+    ) -> IntcodeComputer {
+        let memory: HashMap<_, _> = parse(s).into_iter().enumerate().collect();
+        let mut cpu = IntcodeComputer {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            name: name.to_owned(),
+            instructions: 0,
+            memory,
+            instr_ptr: 0,
+            rel_base: 0,
+            input,
+            output,
+            backend,
+            #[cfg(feature = "jit")]
+            jit: None,
+            smc: None,
+            stack: CallStack::default(),
+            control: None,
+            cancel: None,
+        };
+
+        overrides.into_iter().for_each(|(i, val)| cpu.write(i, val));
+        cpu
+    }
+
+    fn run(&mut self) -> Halt {
+        debug!("vm={} program={} started", self.id, self.name);
+        let halt = self.run_until_stopped();
+        debug!(
+            "vm={} program={} instructions={} halt={}",
+            self.id, self.name, self.instructions, halt
+        );
+        halt
+    }
+
+    fn run_until_stopped(&mut self) -> Halt {
+        loop {
+            let halted = match self.backend {
+                Backend::Interpreter => self.steps(CONTROL_INTERVAL),
+                #[cfg(feature = "jit")]
+                Backend::Jit => jit::run(self, CONTROL_INTERVAL),
             };
+            match halted {
+                Ok(true) => break,
+                Ok(false) if self.cancelled() => return Halt::Cancelled,
+                Ok(false) => self.answer_control(),
+                Err(e) => return Halt::Error(e),
+            }
+        }
+        Halt::Finished
+    }
 
-            overrides.into_iter().for_each(|(i, val)| cpu.write(i, val));
+    /// Runs up to `count` instructions, returning whether the program halted.
+    /// Stops early once cancelled.
+    fn steps(&mut self, count: u64) -> Result<bool, IntcodeError> {
+        for _ in 0..count {
+            if !self.step()? {
+                return Ok(true);
+            }
+            if self.cancelled() {
+                break;
+            }
+        }
+        Ok(false)
+    }
 
-            cpu.run();
-        })
+    fn cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
     }
 
-    fn run(&mut self) {
-        println!("CPU running");
+    fn answer_control(&self) {
+        if let Some(control) = &self.control {
+            for request in control.try_iter() {
+                match request {
+                    Control::Dump(reply) => {
+                        let _ = reply.send(self.memory.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Waits for the next input, or `None` if cancelled first, answering
+    /// control requests meanwhile.
+    fn receive(&self) -> Result<Option<i64>, IntcodeError> {
+        let closed = IntcodeError::InputClosed {
+            addr: self.instr_ptr,
+        };
+        let input = self.input.lock().unwrap();
+        if self.control.is_none() && self.cancel.is_none() {
+            return input.recv().map(Some).map_err(|_| closed);
+        }
         loop {
-            let opcode = self.parse_opcode();
-            match opcode.code {
-                1 => self.opcode1(opcode.modes),
-                2 => self.opcode2(opcode.modes),
-                3 => self.opcode3(opcode.modes),
-                4 => self.opcode4(opcode.modes),
-                5 => self.opcode5(opcode.modes),
-                6 => self.opcode6(opcode.modes),
-                7 => self.opcode7(opcode.modes),
-                8 => self.opcode8(opcode.modes),
-                9 => self.opcode9(opcode.modes),
-                99 => break,
-                x => panic!("Unknown opcode: {}!", x),
+            if self.cancelled() {
+                return Ok(None);
+            }
+            match input.recv_timeout(CONTROL_POLL) {
+                Ok(value) => return Ok(Some(value)),
+                Err(RecvTimeoutError::Timeout) => self.answer_control(),
+                Err(RecvTimeoutError::Disconnected) => return Err(closed),
             }
         }
-        println!("CPU complete");
     }
 
+    /// Executes a single instruction, returning `false` once the program has
+    /// halted.
+    fn step(&mut self) -> Result<bool, IntcodeError> {
+        let opcode = self.parse_opcode()?;
+        self.instructions += 1;
+        if let Some(smc) = &mut self.smc {
+            smc.execute(self.instr_ptr, opcode.modes.len() + 1);
+        }
+        match opcode.code {
+            1 => self.opcode1(opcode.modes)?,
+            2 => self.opcode2(opcode.modes)?,
+            3 => self.opcode3(opcode.modes)?,
+            4 => self.opcode4(opcode.modes)?,
+            5 => self.opcode5(opcode.modes)?,
+            6 => self.opcode6(opcode.modes)?,
+            7 => self.opcode7(opcode.modes)?,
+            8 => self.opcode8(opcode.modes)?,
+            9 => self.opcode9(opcode.modes)?,
+            99 => return Ok(false),
+            x => unreachable!("OpCode::new accepted unknown opcode {}", x),
+        }
+        Ok(true)
+    }
+
+    /// The calls the interpreter has seen made and not yet returned from.
+    /// Code run by `compiled` or the JIT isn't tracked.
+    pub fn backtrace(&self) -> Backtrace {
+        self.stack
+            .backtrace(self.instr_ptr, |location| self.read_addr(location))
+    }
+
+    /// Writes from outside the program, such as overrides.
     pub fn write(&mut self, location: usize, value: i64) {
+        let old = self.read_addr(location);
+        if let Some(smc) = &mut self.smc {
+            smc.write(None, location, old, value);
+        }
         self.memory.insert(location, value);
     }
 
-    fn read(&self, location: usize, param_mode: ParamMode, rw_mode: RWMode) -> i64 {
-        match rw_mode {
+    /// Writes done by the instruction at `instr_ptr`.
+    fn store(&mut self, location: usize, value: i64) {
+        let old = self.read_addr(location);
+        if let Some(smc) = &mut self.smc {
+            smc.write(Some(self.instr_ptr), location, old, value);
+        }
+        self.memory.insert(location, value);
+    }
+
+    fn read(
+        &self,
+        location: usize,
+        param_mode: ParamMode,
+        rw_mode: RWMode,
+    ) -> Result<i64, IntcodeError> {
+        let value = match rw_mode {
             RWMode::Read => match param_mode {
-                ParamMode::Position => self.read_addr(self.read_addr(location) as usize),
+                ParamMode::Position => self.read_addr(self.addr(self.read_addr(location))?),
                 ParamMode::Immediate => self.read_addr(location),
                 ParamMode::Relative => {
-                    self.read_addr((self.rel_base + self.read_addr(location)) as usize)
+                    self.read_addr(self.addr(self.offset(self.read_addr(location))?)?)
                 }
             },
             RWMode::Write => match param_mode {
                 ParamMode::Position => self.read_addr(location),
-                ParamMode::Relative => &self.rel_base + self.read_addr(location),
-                ParamMode::Immediate => panic!("Got Immediate param mode in Write RW mode"),
+                ParamMode::Relative => self.offset(self.read_addr(location))?,
+                ParamMode::Immediate => {
+                    return Err(IntcodeError::ImmediateWrite {
+                        addr: self.instr_ptr,
+                    })
+                }
             },
-        }
+        };
+        Ok(value)
     }
 
     fn read_addr(&self, location: usize) -> i64 {
-        self.memory.get(&location).unwrap_or(&0).clone()
+        *self.memory.get(&location).unwrap_or(&0)
+    }
+
+    fn offset(&self, value: i64) -> Result<i64, IntcodeError> {
+        self.rel_base
+            .checked_add(value)
+            .ok_or(IntcodeError::Overflow {
+                addr: self.instr_ptr,
+            })
     }
 
-    fn parse_opcode(&self) -> OpCode {
-        let value = self.read_addr(self.instr_ptr) as usize;
-        OpCode::new(value)
+    fn addr(&self, value: i64) -> Result<usize, IntcodeError> {
+        if value < 0 {
+            Err(IntcodeError::NegativeAddress {
+                addr: self.instr_ptr,
+                value,
+            })
+        } else {
+            Ok(value as usize)
+        }
+    }
+
+    fn parse_opcode(&self) -> Result<OpCode, IntcodeError> {
+        let value = self.read_addr(self.instr_ptr);
+        OpCode::new(value).ok_or(IntcodeError::BadInstruction {
+            addr: self.instr_ptr,
+            value,
+        })
     }
 
-    fn opcode1(&mut self, modes: Vec<ParamMode>) {
-        let val1 = self.read(self.instr_ptr + 1, modes[0], RWMode::Read);
-        let val2 = self.read(self.instr_ptr + 2, modes[1], RWMode::Read);
-        let pos = self.read(self.instr_ptr + 3, modes[2], RWMode::Write) as usize;
+    fn opcode1(&mut self, modes: Vec<ParamMode>) -> Result<(), IntcodeError> {
+        let val1 = self.read(self.instr_ptr + 1, modes[0], RWMode::Read)?;
+        let val2 = self.read(self.instr_ptr + 2, modes[1], RWMode::Read)?;
+        let pos = self.read(self.instr_ptr + 3, modes[2], RWMode::Write)?;
+        let pos = self.addr(pos)?;
 
-        self.write(pos, val1 + val2);
+        let sum = val1.checked_add(val2).ok_or(IntcodeError::Overflow {
+            addr: self.instr_ptr,
+        })?;
+        self.store(pos, sum);
         self.instr_ptr += *OPCODE_SIZE.get(&1).unwrap() + 1;
+        Ok(())
     }
 
-    fn opcode2(&mut self, modes: Vec<ParamMode>) {
-        let val1 = self.read(self.instr_ptr + 1, modes[0], RWMode::Read);
-        let val2 = self.read(self.instr_ptr + 2, modes[1], RWMode::Read);
-        let pos = self.read(self.instr_ptr + 3, modes[2], RWMode::Write) as usize;
+    fn opcode2(&mut self, modes: Vec<ParamMode>) -> Result<(), IntcodeError> {
+        let val1 = self.read(self.instr_ptr + 1, modes[0], RWMode::Read)?;
+        let val2 = self.read(self.instr_ptr + 2, modes[1], RWMode::Read)?;
+        let pos = self.read(self.instr_ptr + 3, modes[2], RWMode::Write)?;
+        let pos = self.addr(pos)?;
 
-        self.write(pos, val1 * val2);
+        let product = val1.checked_mul(val2).ok_or(IntcodeError::Overflow {
+            addr: self.instr_ptr,
+        })?;
+        self.store(pos, product);
         self.instr_ptr += *OPCODE_SIZE.get(&2).unwrap() + 1;
+        Ok(())
     }
 
-    fn opcode3(&mut self, modes: Vec<ParamMode>) {
-        let pos = self.read(self.instr_ptr + 1, modes[0], RWMode::Write) as usize;
-        let input_value = self.input.lock().unwrap().recv().unwrap();
-        self.write(pos, input_value);
+    fn opcode3(&mut self, modes: Vec<ParamMode>) -> Result<(), IntcodeError> {
+        let pos = self.read(self.instr_ptr + 1, modes[0], RWMode::Write)?;
+        let pos = self.addr(pos)?;
+        // Cancelled while waiting, so leave the instruction to run again.
+        let input_value = match self.receive()? {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+        self.store(pos, input_value);
         self.instr_ptr += *OPCODE_SIZE.get(&3).unwrap() + 1;
+        Ok(())
     }
 
-    fn opcode4(&mut self, modes: Vec<ParamMode>) {
-        let val = self.read(self.instr_ptr + 1, modes[0], RWMode::Read);
-        self.output.lock().unwrap().send(val).unwrap();
+    fn opcode4(&mut self, modes: Vec<ParamMode>) -> Result<(), IntcodeError> {
+        let val = self.read(self.instr_ptr + 1, modes[0], RWMode::Read)?;
+        self.output
+            .lock()
+            .unwrap()
+            .send(val)
+            .map_err(|_| IntcodeError::OutputClosed {
+                addr: self.instr_ptr,
+            })?;
         self.instr_ptr += *OPCODE_SIZE.get(&4).unwrap() + 1;
+        Ok(())
     }
 
-    fn opcode5(&mut self, modes: Vec<ParamMode>) {
-        let val1 = self.read(self.instr_ptr + 1, modes[0], RWMode::Read) as usize;
-        let val2 = self.read(self.instr_ptr + 2, modes[1], RWMode::Read) as usize;
+    fn opcode5(&mut self, modes: Vec<ParamMode>) -> Result<(), IntcodeError> {
+        let val1 = self.read(self.instr_ptr + 1, modes[0], RWMode::Read)?;
+        let val2 = self.read(self.instr_ptr + 2, modes[1], RWMode::Read)?;
 
         if val1 != 0 {
-            self.instr_ptr = val2;
+            let dest = self.addr(val2)?;
+            self.stack.jump(self.instr_ptr, dest);
+            self.instr_ptr = dest;
         } else {
             self.instr_ptr += *OPCODE_SIZE.get(&5).unwrap() + 1;
         }
+        Ok(())
     }
 
-    fn opcode6(&mut self, modes: Vec<ParamMode>) {
-        let val1 = self.read(self.instr_ptr + 1, modes[0], RWMode::Read) as usize;
-        let val2 = self.read(self.instr_ptr + 2, modes[1], RWMode::Read) as usize;
+    fn opcode6(&mut self, modes: Vec<ParamMode>) -> Result<(), IntcodeError> {
+        let val1 = self.read(self.instr_ptr + 1, modes[0], RWMode::Read)?;
+        let val2 = self.read(self.instr_ptr + 2, modes[1], RWMode::Read)?;
 
         if val1 == 0 {
-            self.instr_ptr = val2;
+            let dest = self.addr(val2)?;
+            self.stack.jump(self.instr_ptr, dest);
+            self.instr_ptr = dest;
         } else {
             self.instr_ptr += *OPCODE_SIZE.get(&6).unwrap() + 1;
         }
+        Ok(())
     }
 
-    fn opcode7(&mut self, modes: Vec<ParamMode>) {
-        let val1 = self.read(self.instr_ptr + 1, modes[0], RWMode::Read);
-        let val2 = self.read(self.instr_ptr + 2, modes[1], RWMode::Read);
-        let pos = self.read(self.instr_ptr + 3, modes[2], RWMode::Write) as usize;
+    fn opcode7(&mut self, modes: Vec<ParamMode>) -> Result<(), IntcodeError> {
+        let val1 = self.read(self.instr_ptr + 1, modes[0], RWMode::Read)?;
+        let val2 = self.read(self.instr_ptr + 2, modes[1], RWMode::Read)?;
+        let pos = self.read(self.instr_ptr + 3, modes[2], RWMode::Write)?;
+        let pos = self.addr(pos)?;
 
         if val1 < val2 {
-            self.write(pos, 1);
+            self.store(pos, 1);
         } else {
-            self.write(pos, 0);
+            self.store(pos, 0);
         }
         self.instr_ptr += *OPCODE_SIZE.get(&7).unwrap() + 1;
+        Ok(())
     }
 
-    fn opcode8(&mut self, modes: Vec<ParamMode>) {
-        let val1 = self.read(self.instr_ptr + 1, modes[0], RWMode::Read);
-        let val2 = self.read(self.instr_ptr + 2, modes[1], RWMode::Read);
-        let pos = self.read(self.instr_ptr + 3, modes[2], RWMode::Write) as usize;
+    fn opcode8(&mut self, modes: Vec<ParamMode>) -> Result<(), IntcodeError> {
+        let val1 = self.read(self.instr_ptr + 1, modes[0], RWMode::Read)?;
+        let val2 = self.read(self.instr_ptr + 2, modes[1], RWMode::Read)?;
+        let pos = self.read(self.instr_ptr + 3, modes[2], RWMode::Write)?;
+        let pos = self.addr(pos)?;
 
         if val1 == val2 {
-            self.write(pos, 1);
+            self.store(pos, 1);
         } else {
-            self.write(pos, 0);
+            self.store(pos, 0);
         }
         self.instr_ptr += *OPCODE_SIZE.get(&8).unwrap() + 1;
+        Ok(())
     }
 
-    fn opcode9(&mut self, modes: Vec<ParamMode>) {
-        let val1 = self.read(self.instr_ptr + 1, modes[0], RWMode::Read);
-        self.rel_base += val1;
+    fn opcode9(&mut self, modes: Vec<ParamMode>) -> Result<(), IntcodeError> {
+        let val1 = self.read(self.instr_ptr + 1, modes[0], RWMode::Read)?;
+        let rel_base = self.offset(val1)?;
+        let memory = &self.memory;
+        self.stack.arb(self.instr_ptr, self.rel_base, rel_base, |location| {
+            *memory.get(&location).unwrap_or(&0)
+        });
+        self.rel_base = rel_base;
         self.instr_ptr += *OPCODE_SIZE.get(&9).unwrap() + 1;
+        Ok(())
     }
 }
 
-static OPCODE_SIZE: phf::Map<u32, usize> = phf_map! {
-    1u32 => 3,
-    2u32 => 3,
-    3u32 => 1,
-    4u32 => 1,
-    5u32 => 2,
-    6u32 => 2,
-    7u32 => 3,
-    8u32 => 3,
-    9u32 => 1,
-    99u32 => 0,
-};
-
-impl OpCode {
-    pub fn new(value: usize) -> OpCode {
-        let code = (value % 100) as u32;
-        let size = *OPCODE_SIZE.get(&code).unwrap();
-
-        let mut modes = Vec::new();
-        let mut codes = value / 100;
-
-        for _ in 0..size {
-            let param_mode = match codes % 10 {
-                0 => ParamMode::Position,
-                1 => ParamMode::Immediate,
-                2 => ParamMode::Relative,
-                x => panic!("Unknown param mode {}", x),
-            };
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl IntcodeHandle {
+    /// A copy of the VM's memory as it is now, or `None` once it has stopped.
+    pub fn dump(&self) -> Option<HashMap<usize, i64>> {
+        let (reply, rx_reply) = mpsc::channel();
+        self.control.send(Control::Dump(reply)).ok()?;
+        rx_reply.recv().ok()
+    }
+
+    /// Waits for the VM to stop.
+    pub fn join(self) -> VmState {
+        self.thread_handle.join().unwrap()
+    }
+
+    /// Tells the VM to stop, without waiting for it to.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    pub fn cancel_and_join(self) -> VmState {
+        self.cancel();
+        self.join()
+    }
+
+    /// The token the VM checks, for cancelling it from elsewhere.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+}
 
-            modes.push(param_mode);
-            codes /= 10;
+impl RunResult {
+    pub fn read(&self, location: usize) -> i64 {
+        *self.memory.get(&location).unwrap_or(&0)
+    }
+}
+
+impl VmState {
+    pub fn read(&self, location: usize) -> i64 {
+        *self.memory.get(&location).unwrap_or(&0)
+    }
+}
+
+impl fmt::Display for Halt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Halt::Finished => write!(f, "finished"),
+            Halt::Error(e) => write!(f, "error: {}", e),
+            Halt::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntcodeError::BadInstruction { addr, value } => {
+                write!(f, "Bad instruction {} at {}", value, addr)
+            }
+            IntcodeError::ImmediateWrite { addr } => {
+                write!(f, "Got Immediate param mode for a write at {}", addr)
+            }
+            IntcodeError::NegativeAddress { addr, value } => {
+                write!(f, "Negative address {} used at {}", value, addr)
+            }
+            IntcodeError::InputClosed { addr } => {
+                write!(f, "Input closed while reading at {}", addr)
+            }
+            IntcodeError::OutputClosed { addr } => {
+                write!(f, "Output closed while writing at {}", addr)
+            }
+            IntcodeError::Overflow { addr } => write!(f, "Arithmetic overflow at {}", addr),
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_returns_final_memory() {
+        let input = include_str!("../inputs/day2.txt");
+        let state = IntcodeComputer::new("day2", input, vec![(1, 12), (2, 2)]).join();
+        assert_eq!(state.halt, Halt::Finished);
+        assert_eq!(state.read(0), 6568671);
+        assert_eq!(state.read(state.instr_ptr), 99);
+    }
+
+    #[test]
+    fn dumps_memory_while_waiting_for_input() {
+        dumps_memory_while_waiting_for_input_with(Backend::Interpreter);
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn dumps_memory_while_jit_waits_for_input() {
+        dumps_memory_while_waiting_for_input_with(Backend::Jit);
+    }
+
+    fn dumps_memory_while_waiting_for_input_with(backend: Backend) {
+        let program = "1101,2,3,9,3,10,99,0,0,0,0";
+        let handle = IntcodeComputer::new_with_backend("test", program, Vec::new(), backend);
+        let memory = handle.dump().unwrap();
+        assert_eq!(memory[&9], 5);
+        assert_eq!(memory[&10], 0);
+
+        handle.tx_input.send(7).unwrap();
+        let state = handle.join();
+        assert_eq!(state.read(10), 7);
+        assert_eq!(state.halt, Halt::Finished);
+    }
+
+    #[test]
+    fn join_reports_errors() {
+        let state = IntcodeComputer::new("test", "109,5,104,1,98", Vec::new()).join();
+        assert_eq!(
+            state.halt,
+            Halt::Error(IntcodeError::BadInstruction { addr: 4, value: 98 })
+        );
+        assert_eq!((state.instr_ptr, state.rel_base), (4, 5));
+    }
+
+    #[test]
+    fn cancels_a_program_that_never_halts() {
+        let handle = IntcodeComputer::new("test", "1001,5,1,5,1105,1,0", Vec::new());
+        let state = handle.cancel_and_join();
+        assert_eq!(state.halt, Halt::Cancelled);
+        assert!(state.read(5) > 0);
+    }
+
+    #[test]
+    fn cancels_a_program_waiting_for_input() {
+        let handle = IntcodeComputer::new("test", "3,0,99", Vec::new());
+        let state = handle.cancel_and_join();
+        assert_eq!((state.halt, state.instr_ptr), (Halt::Cancelled, 0));
+    }
+
+    #[test]
+    fn error_cancels_the_rest_of_a_group() {
+        let cancel = CancelToken::default();
+        let (tx, rx) = mpsc::channel();
+        let (tx_output, _rx_output) = mpsc::channel();
+        let (input, output) = (Arc::new(Mutex::new(rx)), Arc::new(Mutex::new(tx_output)));
+
+        let waiting = IntcodeComputer::from_with_cancel(
+            "waiting",
+            "3,0,99".to_owned(),
+            Vec::new(),
+            input,
+            output.clone(),
+            cancel.clone(),
+        );
+        let failing = IntcodeComputer::from_with_cancel(
+            "failing",
+            "98".to_owned(),
+            Vec::new(),
+            Arc::new(Mutex::new(mpsc::channel().1)),
+            output,
+            cancel,
+        );
 
-        OpCode { code, modes }
+        assert!(matches!(failing.join().unwrap().halt, Halt::Error(_)));
+        assert_eq!(waiting.join().unwrap().halt, Halt::Cancelled);
+        drop(tx);
     }
 }
@@ -0,0 +1,353 @@
+//! Control-flow graph recovery. Code is found by following execution from
+//! address 0 through immediate-mode jump targets; anything never reached that
+//! way is reported as unreachable, which in practice is mostly data.
+//!
+//! Calls are recognised by the convention the puzzle programs are compiled
+//! with: the return address is written into a relative slot as a constant
+//! (`mul 1, ret, [rb+0]`), immediately followed by an unconditional jump to
+//! the callee. A function returns with an unconditional jump through a
+//! relative slot (`jz 0, [rb+0]`).
+
+use super::disasm::{Instruction, Operand};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// Runs on into the next block without a jump.
+    Fallthrough(usize),
+    /// An unconditional jump, `None` when the target comes from memory.
+    Jump(Option<usize>),
+    Branch {
+        taken: Option<usize>,
+        fallthrough: usize,
+    },
+    Call {
+        target: usize,
+        ret: usize,
+    },
+    Return,
+    Halt,
+    /// The next word is not a valid instruction.
+    Invalid(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub start: usize,
+    pub instrs: Vec<Instruction>,
+    pub exit: Exit,
+}
+
+#[derive(Debug)]
+pub struct Cfg {
+    pub blocks: BTreeMap<usize, Block>,
+    /// Entry address of each function, including 0, with the blocks that
+    /// belong to it.
+    pub functions: BTreeMap<usize, Vec<usize>>,
+    pub unreachable: Vec<Range<usize>>,
+}
+
+/// How control leaves a single instruction.
+#[derive(Debug, Clone, Copy)]
+enum Flow {
+    Next,
+    Exit(Exit),
+}
+
+impl Exit {
+    pub fn successors(&self) -> Vec<usize> {
+        match *self {
+            Exit::Fallthrough(next) | Exit::Jump(Some(next)) => vec![next],
+            Exit::Branch { taken, fallthrough } => {
+                taken.into_iter().chain(Some(fallthrough)).collect()
+            }
+            Exit::Call { target, ret } => vec![target, ret],
+            Exit::Jump(None) | Exit::Return | Exit::Halt | Exit::Invalid(_) => Vec::new(),
+        }
+    }
+
+    /// Successors within the same function, so calls only continue at the
+    /// return address.
+    pub fn local_successors(&self) -> Vec<usize> {
+        match *self {
+            Exit::Call { ret, .. } => vec![ret],
+            _ => self.successors(),
+        }
+    }
+}
+
+impl Cfg {
+    pub fn build(program: &[i64]) -> Cfg {
+        let mut instrs: BTreeMap<usize, (Instruction, Flow)> = BTreeMap::new();
+        let mut invalid = BTreeSet::new();
+        let mut leaders = BTreeSet::new();
+        let mut calls = BTreeSet::new();
+        let mut work: Vec<(usize, Option<Instruction>)> = vec![(0, None)];
+        leaders.insert(0);
+
+        while let Some((addr, prev)) = work.pop() {
+            if instrs.contains_key(&addr) || invalid.contains(&addr) {
+                continue;
+            }
+            let instr = match Instruction::decode(program, addr) {
+                Some(instr) => instr,
+                None => {
+                    invalid.insert(addr);
+                    continue;
+                }
+            };
+
+            let flow = flow(&instr, prev.as_ref());
+            let next = instr.next();
+            match flow {
+                Flow::Next => work.push((next, Some(instr.clone()))),
+                Flow::Exit(exit) => {
+                    if let Exit::Call { target, .. } = exit {
+                        calls.insert(target);
+                    }
+                    for target in exit.successors() {
+                        leaders.insert(target);
+                        work.push((target, None));
+                    }
+                }
+            }
+            instrs.insert(addr, (instr, flow));
+        }
+
+        let blocks = leaders
+            .iter()
+            .map(|&start| (start, Cfg::block_at(start, &instrs, &leaders)))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut functions = BTreeMap::new();
+        let mut owner = BTreeSet::new();
+        for &entry in Some(0).iter().chain(calls.iter()) {
+            let mut members = Vec::new();
+            let mut stack = vec![entry];
+            while let Some(start) = stack.pop() {
+                if !owner.insert(start) {
+                    continue;
+                }
+                members.push(start);
+                stack.extend(blocks[&start].exit.local_successors());
+            }
+            members.sort();
+            functions.insert(entry, members);
+        }
+
+        let mut covered = vec![false; program.len()];
+        for (instr, _) in instrs.values() {
+            let end = instr.next().min(program.len());
+            covered[instr.addr..end].iter_mut().for_each(|c| *c = true);
+        }
+        let mut unreachable = Vec::new();
+        let mut a = 0;
+        while a < covered.len() {
+            if covered[a] {
+                a += 1;
+                continue;
+            }
+            let start = a;
+            while a < covered.len() && !covered[a] {
+                a += 1;
+            }
+            unreachable.push(start..a);
+        }
+
+        Cfg {
+            blocks,
+            functions,
+            unreachable,
+        }
+    }
+
+    fn block_at(
+        start: usize,
+        instrs: &BTreeMap<usize, (Instruction, Flow)>,
+        leaders: &BTreeSet<usize>,
+    ) -> Block {
+        let mut block = Block {
+            start,
+            instrs: Vec::new(),
+            exit: Exit::Invalid(start),
+        };
+        let mut addr = start;
+        while let Some((instr, flow)) = instrs.get(&addr) {
+            block.instrs.push(instr.clone());
+            match flow {
+                Flow::Exit(exit) => {
+                    block.exit = *exit;
+                    return block;
+                }
+                Flow::Next => {
+                    addr = instr.next();
+                    if leaders.contains(&addr) {
+                        block.exit = Exit::Fallthrough(addr);
+                        return block;
+                    }
+                }
+            }
+        }
+        block.exit = Exit::Invalid(addr);
+        block
+    }
+
+    /// The graph in Graphviz DOT format, with one cluster per function.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph intcode {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=monospace];").unwrap();
+
+        for (entry, members) in &self.functions {
+            let name = if *entry == 0 {
+                "main".to_owned()
+            } else {
+                format!("fn_{}", entry)
+            };
+            writeln!(dot, "    subgraph cluster_{} {{", entry).unwrap();
+            writeln!(dot, "        label=\"{}\";", name).unwrap();
+            for start in members {
+                let block = &self.blocks[start];
+                let mut label = String::new();
+                for instr in &block.instrs {
+                    write!(label, "{}\\l", instr).unwrap();
+                }
+                match block.exit {
+                    Exit::Return => label.push_str("return\\l"),
+                    Exit::Jump(None) => label.push_str("indirect jump\\l"),
+                    Exit::Invalid(addr) => write!(label, "invalid at {}\\l", addr).unwrap(),
+                    _ => {}
+                }
+                writeln!(dot, "        b{} [label=\"{}\"];", start, label).unwrap();
+            }
+            writeln!(dot, "    }}").unwrap();
+        }
+
+        for region in &self.unreachable {
+            writeln!(
+                dot,
+                "    u{} [label=\"unreachable {}..{}\", style=filled, fillcolor=lightgray];",
+                region.start, region.start, region.end
+            )
+            .unwrap();
+        }
+
+        for block in self.blocks.values() {
+            let from = block.start;
+            match block.exit {
+                Exit::Fallthrough(to) | Exit::Jump(Some(to)) => {
+                    writeln!(dot, "    b{} -> b{};", from, to).unwrap()
+                }
+                Exit::Branch { taken, fallthrough } => {
+                    if let Some(to) = taken {
+                        writeln!(dot, "    b{} -> b{} [label=\"taken\"];", from, to).unwrap();
+                    }
+                    writeln!(dot, "    b{} -> b{} [label=\"else\"];", from, fallthrough).unwrap();
+                }
+                Exit::Call { target, ret } => {
+                    writeln!(
+                        dot,
+                        "    b{} -> b{} [label=\"call\", style=dashed];",
+                        from, target
+                    )
+                    .unwrap();
+                    writeln!(dot, "    b{} -> b{} [label=\"after call\"];", from, ret).unwrap();
+                }
+                Exit::Jump(None) | Exit::Return | Exit::Halt | Exit::Invalid(_) => {}
+            }
+        }
+
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+fn flow(instr: &Instruction, prev: Option<&Instruction>) -> Flow {
+    let next = instr.next();
+    match instr.code {
+        99 => Flow::Exit(Exit::Halt),
+        5 | 6 => {
+            let target = match instr.operands[1] {
+                Operand::Immediate(t) if t >= 0 => Some(t as usize),
+                _ => None,
+            };
+            match instr.always_jumps() {
+                Some(false) => Flow::Next,
+                Some(true) => {
+                    if let Operand::Relative(_) = instr.operands[1] {
+                        return Flow::Exit(Exit::Return);
+                    }
+                    match (target, prev.and_then(return_address)) {
+                        (Some(target), Some(ret)) if ret == next => {
+                            Flow::Exit(Exit::Call { target, ret })
+                        }
+                        _ => Flow::Exit(Exit::Jump(target)),
+                    }
+                }
+                None => Flow::Exit(Exit::Branch {
+                    taken: target,
+                    fallthrough: next,
+                }),
+            }
+        }
+        _ => Flow::Next,
+    }
+}
+
+/// The constant an instruction stores into a relative slot, if it looks like
+/// the return address half of a call.
+fn return_address(instr: &Instruction) -> Option<usize> {
+    match instr.operands.last() {
+        Some(Operand::Relative(_)) => instr
+            .constant_result()
+            .filter(|&v| v >= 0)
+            .map(|v| v as usize),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse;
+    use super::*;
+
+    // main: store 7 as the return address, call 11, then halt at 7.
+    // fn_11: output 7 and return through [rb+0]. Addresses 8 to 10 are data.
+    const PROGRAM: &str = "21101,0,7,0,1105,1,11,99,0,0,42,104,7,2105,1,0";
+
+    #[test]
+    fn finds_calls_and_returns() {
+        let cfg = Cfg::build(&parse(PROGRAM));
+
+        assert_eq!(cfg.blocks[&0].exit, Exit::Call { target: 11, ret: 7 });
+        assert_eq!(cfg.blocks[&11].exit, Exit::Return);
+        assert_eq!(
+            cfg.functions.keys().cloned().collect::<Vec<_>>(),
+            vec![0, 11]
+        );
+        assert_eq!(cfg.functions[&0], vec![0, 7]);
+    }
+
+    #[test]
+    fn reports_unreachable_regions() {
+        let cfg = Cfg::build(&parse(PROGRAM));
+        assert_eq!(cfg.unreachable, vec![8..11]);
+    }
+
+    #[test]
+    fn splits_blocks_at_branch_targets() {
+        // Spin on the jump while the input is non-zero.
+        let cfg = Cfg::build(&parse("3,20,1005,20,2,99"));
+        assert_eq!(
+            cfg.blocks[&2].exit,
+            Exit::Branch {
+                taken: Some(2),
+                fallthrough: 5,
+            }
+        );
+        assert_eq!(cfg.blocks[&0].exit, Exit::Fallthrough(2));
+        assert!(cfg.to_dot().contains("b2 -> b2 [label=\"taken\"];"));
+    }
+}
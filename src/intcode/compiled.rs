@@ -0,0 +1,293 @@
+//! Programs compiled to Rust at build time by `transpile`, and the machine
+//! they run on. `build.rs` lists what gets compiled: `DAY2` from the puzzle
+//! input, and `EXAMPLES` from `examples::ALL` for the conformance tests.
+//!
+//! Memory is a flat `Vec` rather than the interpreter's map. When compiled
+//! code can no longer be trusted, the machine's state is handed to an
+//! `IntcodeComputer` that finishes the run.
+
+use super::stack::{Backtrace, CallStack};
+use super::NEXT_ID;
+use super::{Backend, Halt, IntcodeComputer, IntcodeError, RunResult, VmState};
+use log::warn;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+
+pub use self::generated::*;
+
+#[allow(clippy::all, dead_code, unused_labels)]
+mod generated {
+    use super::{Compiled, IntcodeError, Machine};
+
+    include!(concat!(env!("OUT_DIR"), "/compiled.rs"));
+}
+
+/// Addresses from here on are kept in a map instead of growing the `Vec`.
+const DENSE_LIMIT: usize = 1 << 20;
+
+#[derive(Debug)]
+pub struct Compiled {
+    /// The name of the static, lowercased, for log events.
+    name: &'static str,
+    pub program: &'static [i64],
+    /// Which addresses hold an opcode the compiled code depends on.
+    opcodes: &'static [bool],
+    body: fn(&mut Machine) -> Result<(), IntcodeError>,
+}
+
+/// The state of a compiled program as it runs. The generated code names every
+/// address it touches, so each accessor takes the address of the instruction
+/// doing the access for its errors.
+#[derive(Debug)]
+pub struct Machine {
+    memory: Vec<i64>,
+    sparse: HashMap<usize, i64>,
+    instr_ptr: usize,
+    rel_base: i64,
+    input: Arc<Mutex<Receiver<i64>>>,
+    output: Arc<Mutex<Sender<i64>>>,
+    compiled: &'static Compiled,
+    /// Set once an opcode word has changed, so the compiled code is stale.
+    dirty: bool,
+}
+
+impl Compiled {
+    /// The compiled counterpart of `IntcodeComputer::from`.
+    pub fn from(
+        &'static self,
+        overrides: Vec<(usize, i64)>,
+        input: Arc<Mutex<Receiver<i64>>>,
+        output: Arc<Mutex<Sender<i64>>>,
+    ) -> JoinHandle<VmState> {
+        thread::spawn(move || {
+            let mut m = self.load(overrides, input, output);
+            let halt = match (self.body)(&mut m) {
+                Ok(()) => Halt::Finished,
+                Err(e) => {
+                    warn!("{}", e);
+                    Halt::Error(e)
+                }
+            };
+
+            // Calls made by compiled code aren't tracked.
+            VmState {
+                backtrace: Backtrace {
+                    instr_ptr: m.instr_ptr,
+                    frames: Vec::new(),
+                },
+                instr_ptr: m.instr_ptr,
+                rel_base: m.rel_base,
+                memory: m.into_memory(),
+                halt,
+            }
+        })
+    }
+
+    /// The compiled counterpart of `IntcodeComputer::execute`.
+    pub fn execute(&'static self, overrides: Vec<(usize, i64)>, inputs: &[i64]) -> RunResult {
+        let (tx_input, rx_input) = mpsc::channel();
+        let (tx_output, rx_output) = mpsc::channel();
+        inputs.iter().for_each(|&v| tx_input.send(v).unwrap());
+        drop(tx_input);
+
+        let mut m = self.load(
+            overrides,
+            Arc::new(Mutex::new(rx_input)),
+            Arc::new(Mutex::new(tx_output)),
+        );
+        let result = (self.body)(&mut m);
+
+        RunResult {
+            memory: m.into_memory(),
+            output: rx_output.try_iter().collect(),
+            result,
+            self_modifications: Vec::new(),
+        }
+    }
+
+    fn load(
+        &'static self,
+        overrides: Vec<(usize, i64)>,
+        input: Arc<Mutex<Receiver<i64>>>,
+        output: Arc<Mutex<Sender<i64>>>,
+    ) -> Machine {
+        let mut m = Machine {
+            memory: self.program.to_vec(),
+            sparse: HashMap::new(),
+            instr_ptr: 0,
+            rel_base: 0,
+            input,
+            output,
+            compiled: self,
+            dirty: false,
+        };
+
+        overrides.into_iter().for_each(|(i, val)| {
+            m.store(i, val);
+        });
+        m
+    }
+}
+
+impl Machine {
+    fn word(&self, location: usize) -> i64 {
+        match self.memory.get(location) {
+            Some(&value) => value,
+            None => *self.sparse.get(&location).unwrap_or(&0),
+        }
+    }
+
+    fn addr(&self, at: usize, value: i64) -> Result<usize, IntcodeError> {
+        if value < 0 {
+            Err(IntcodeError::NegativeAddress { addr: at, value })
+        } else {
+            Ok(value as usize)
+        }
+    }
+
+    fn offset(&self, at: usize, value: i64) -> Result<i64, IntcodeError> {
+        self.rel_base
+            .checked_add(value)
+            .ok_or(IntcodeError::Overflow { addr: at })
+    }
+
+    fn load(&self, at: usize, ptr: i64) -> Result<i64, IntcodeError> {
+        Ok(self.word(self.addr(at, ptr)?))
+    }
+
+    fn load_rel(&self, at: usize, offset: i64) -> Result<i64, IntcodeError> {
+        Ok(self.word(self.addr(at, self.offset(at, offset)?)?))
+    }
+
+    fn target(&self, at: usize, ptr: i64) -> Result<usize, IntcodeError> {
+        self.addr(at, ptr)
+    }
+
+    fn add(&self, at: usize, a: i64, b: i64) -> Result<i64, IntcodeError> {
+        a.checked_add(b).ok_or(IntcodeError::Overflow { addr: at })
+    }
+
+    fn mul(&self, at: usize, a: i64, b: i64) -> Result<i64, IntcodeError> {
+        a.checked_mul(b).ok_or(IntcodeError::Overflow { addr: at })
+    }
+
+    /// Writes `value`, returning `true` if that left the compiled code stale.
+    fn store(&mut self, location: usize, value: i64) -> bool {
+        if location < self.memory.len() {
+            self.memory[location] = value;
+        } else if location < DENSE_LIMIT {
+            self.memory.resize(location + 1, 0);
+            self.memory[location] = value;
+        } else {
+            self.sparse.insert(location, value);
+        }
+
+        if self.compiled.opcodes.get(location) == Some(&true)
+            && self.compiled.program[location] != value
+        {
+            self.dirty = true;
+        }
+        self.dirty
+    }
+
+    fn input(&mut self, at: usize) -> Result<i64, IntcodeError> {
+        self.input
+            .lock()
+            .unwrap()
+            .recv()
+            .map_err(|_| IntcodeError::InputClosed { addr: at })
+    }
+
+    fn output(&mut self, at: usize, value: i64) -> Result<(), IntcodeError> {
+        self.output
+            .lock()
+            .unwrap()
+            .send(value)
+            .map_err(|_| IntcodeError::OutputClosed { addr: at })
+    }
+
+    fn arb(&mut self, at: usize, value: i64) -> Result<(), IntcodeError> {
+        self.rel_base = self.offset(at, value)?;
+        Ok(())
+    }
+
+    fn jump(&self, at: usize, dest: i64) -> Result<usize, IntcodeError> {
+        self.addr(at, dest)
+    }
+
+    /// Finishes the run on the interpreter from the current state.
+    fn interpret(&mut self) -> Result<(), IntcodeError> {
+        let mut cpu = IntcodeComputer {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            name: self.compiled.name.to_owned(),
+            instructions: 0,
+            memory: self.take_memory(),
+            instr_ptr: self.instr_ptr,
+            rel_base: self.rel_base,
+            input: self.input.clone(),
+            output: self.output.clone(),
+            backend: Backend::Interpreter,
+            #[cfg(feature = "jit")]
+            jit: None,
+            smc: None,
+            stack: CallStack::default(),
+            control: None,
+            cancel: None,
+        };
+        let result = loop {
+            match cpu.step() {
+                Ok(true) => {}
+                Ok(false) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+
+        self.sparse = cpu.memory;
+        self.instr_ptr = cpu.instr_ptr;
+        self.rel_base = cpu.rel_base;
+        result
+    }
+
+    fn take_memory(&mut self) -> HashMap<usize, i64> {
+        let mut memory: HashMap<_, _> = self.memory.drain(..).enumerate().collect();
+        memory.extend(self.sparse.drain());
+        memory
+    }
+
+    fn into_memory(mut self) -> HashMap<usize, i64> {
+        self.take_memory()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn day2_matches_interpreter() {
+        let input = include_str!("../../inputs/day2.txt");
+        for &(noun, verb) in &[(12, 2), (0, 0), (99, 99), (39, 51)] {
+            let overrides = vec![(1, noun), (2, verb)];
+            let expected = IntcodeComputer::execute("day2", input, overrides.clone(), &[]);
+            let run = DAY2.execute(overrides, &[]);
+            assert_eq!(run.result, expected.result);
+            assert_eq!(run.read(0), expected.read(0));
+        }
+    }
+
+    #[test]
+    fn falls_back_when_an_opcode_is_overwritten() {
+        // Address 4 holds the opcode of the second add; making it a mul
+        // leaves the compiled code stale before it starts.
+        let input = include_str!("../../inputs/day2.txt");
+        let overrides = vec![(1, 12), (2, 2), (4, 2)];
+        let expected = IntcodeComputer::execute("day2", input, overrides.clone(), &[]);
+        let run = DAY2.execute(overrides, &[]);
+        assert_eq!(run.result, expected.result);
+        assert_eq!(run.memory, expected.memory);
+    }
+}
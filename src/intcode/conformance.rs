@@ -0,0 +1,159 @@
+//! The published Intcode examples from days 2, 5 and 9. Every execution
+//! backend and memory model has to pass all of them, so new ones get added to
+//! `BACKENDS` rather than getting their own copy of these tests.
+
+use super::examples::*;
+use super::{compiled, parse, IntcodeComputer, RunResult};
+
+struct Outcome {
+    memory: Vec<i64>,
+    output: Vec<i64>,
+}
+
+type Backend = fn(&str, &[i64]) -> Outcome;
+
+#[cfg(not(feature = "jit"))]
+static BACKENDS: &[(&str, Backend)] = &[("interpreter", interpreter), ("compiled", compiled)];
+#[cfg(feature = "jit")]
+static BACKENDS: &[(&str, Backend)] = &[
+    ("interpreter", interpreter),
+    ("compiled", compiled),
+    ("jit", jit),
+];
+
+fn interpreter(program: &str, inputs: &[i64]) -> Outcome {
+    outcome(
+        program,
+        IntcodeComputer::execute("example", program, Vec::new(), inputs),
+    )
+}
+
+#[cfg(feature = "jit")]
+fn jit(program: &str, inputs: &[i64]) -> Outcome {
+    let backend = super::Backend::Jit;
+    let run =
+        IntcodeComputer::execute_with_backend("example", program, Vec::new(), inputs, backend);
+    outcome(program, run)
+}
+
+/// Runs the copy of `program` that the build script compiled from
+/// `examples::ALL`, which it compiles in order.
+fn compiled(program: &str, inputs: &[i64]) -> Outcome {
+    let i = ALL
+        .iter()
+        .position(|&example| example == program)
+        .expect("example missing from examples::ALL");
+    outcome(program, compiled::EXAMPLES[i].execute(Vec::new(), inputs))
+}
+
+fn outcome(program: &str, run: RunResult) -> Outcome {
+    run.result.unwrap();
+
+    Outcome {
+        memory: (0..parse(program).len()).map(|i| run.read(i)).collect(),
+        output: run.output,
+    }
+}
+
+fn assert_memory(program: &str, expected: &str) {
+    for (name, backend) in BACKENDS {
+        let outcome = backend(program, &[]);
+        assert_eq!(outcome.memory, parse(expected), "{} on {}", name, program);
+    }
+}
+
+fn assert_output(program: &str, input: &[i64], expected: &[i64]) {
+    for (name, backend) in BACKENDS {
+        let outcome = backend(program, input);
+        assert_eq!(
+            outcome.output, expected,
+            "{} on {} with input {:?}",
+            name, program, input
+        );
+    }
+}
+
+#[test]
+fn day2_add() {
+    assert_memory(DAY2_ADD, "2,0,0,0,99");
+}
+
+#[test]
+fn day2_mul() {
+    assert_memory(DAY2_MUL, "2,3,0,6,99");
+    assert_memory(DAY2_SQUARE, "2,4,4,5,99,9801");
+}
+
+#[test]
+fn day2_overwrites_own_halt() {
+    assert_memory(DAY2_OVERWRITES_HALT, "30,1,1,4,2,5,6,0,99");
+}
+
+#[test]
+fn day2_walkthrough() {
+    assert_memory(DAY2_WALKTHROUGH, "3500,9,10,70,2,3,11,0,99,30,40,50");
+}
+
+#[test]
+fn day5_echo() {
+    assert_output(DAY5_ECHO, &[42], &[42]);
+    assert_output(DAY5_ECHO, &[-7], &[-7]);
+}
+
+#[test]
+fn day5_immediate_mode() {
+    assert_memory(DAY5_MUL_IMMEDIATE, "1002,4,3,4,99");
+    assert_memory(DAY5_NEGATIVE_IMMEDIATE, "1101,100,-1,4,99");
+}
+
+#[test]
+fn day5_equal_to_8() {
+    for program in &[DAY5_EQUAL_POSITION, DAY5_EQUAL_IMMEDIATE] {
+        assert_output(program, &[8], &[1]);
+        assert_output(program, &[7], &[0]);
+        assert_output(program, &[9], &[0]);
+    }
+}
+
+#[test]
+fn day5_less_than_8() {
+    for program in &[DAY5_LESS_POSITION, DAY5_LESS_IMMEDIATE] {
+        assert_output(program, &[7], &[1]);
+        assert_output(program, &[8], &[0]);
+        assert_output(program, &[-100], &[1]);
+    }
+}
+
+#[test]
+fn day5_jumps() {
+    for program in &[DAY5_JUMP_POSITION, DAY5_JUMP_IMMEDIATE] {
+        assert_output(program, &[0], &[0]);
+        assert_output(program, &[5], &[1]);
+        assert_output(program, &[-3], &[1]);
+    }
+}
+
+#[test]
+fn day5_compare_with_8() {
+    let program = DAY5_COMPARE_WITH_8;
+    assert_output(program, &[-5], &[999]);
+    assert_output(program, &[7], &[999]);
+    assert_output(program, &[8], &[1000]);
+    assert_output(program, &[9], &[1001]);
+    assert_output(program, &[100], &[1001]);
+}
+
+#[test]
+fn day9_quine() {
+    assert_output(DAY9_QUINE, &[], &parse(DAY9_QUINE));
+}
+
+#[test]
+fn day9_sixteen_digits() {
+    assert_output(DAY9_SIXTEEN_DIGITS, &[], &[1219070632396864]);
+}
+
+#[test]
+fn day9_large_number() {
+    assert_output(DAY9_LARGE_NUMBER, &[], &[1125899906842624]);
+}
@@ -0,0 +1,605 @@
+//! Lifts a program into structured pseudo-code on top of the recovered CFG.
+//! Blocks are laid out in address order, which is how the puzzle programs
+//! were compiled, so loops and conditionals are recovered from the shape of
+//! the jumps: a backward `jnz`/`jz` closes a `do`/`while`, a forward one
+//! skips over the body of an `if`, and a forward jump at the end of that body
+//! skips an `else`. Anything else falls back to labels and `goto`. Blocks
+//! that can only run into an invalid instruction are data some jump happens
+//! to point at, and are left out of the structure and dumped raw at the end.
+//!
+//! Relative slots are named from the calling convention. A function starts
+//! with `arb K`, so below the new base sit the return address (`ret_addr`)
+//! and slots `1..K`, which become `argN` if they are read before written
+//! and `localN` otherwise. Slots at or above the base are set up for calls
+//! this function makes and are named `outN`.
+
+use super::cfg::{Cfg, Exit};
+use super::disasm::{Instruction, Operand};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+use std::ops::Range;
+
+enum Line {
+    Label(usize),
+    Code(usize, String),
+}
+
+struct Function<'a> {
+    cfg: &'a Cfg,
+    args: &'a HashMap<usize, BTreeSet<i64>>,
+    entry: usize,
+    frame: i64,
+    deltas: HashMap<usize, Option<i64>>,
+    gotos: BTreeSet<usize>,
+    lines: Vec<Line>,
+}
+
+/// Pseudo-code for every function reachable from address 0.
+pub fn decompile(program: &[i64]) -> String {
+    let cfg = Cfg::build(program);
+    let data = data_blocks(&cfg);
+    let code = cfg
+        .functions
+        .iter()
+        .map(|(&entry, members)| {
+            let members = members.iter().filter(|b| !data.contains(b)).copied();
+            (entry, members.collect::<Vec<_>>())
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let frames = code
+        .iter()
+        .map(|(&entry, members)| (entry, frame_size(&cfg, members)))
+        .collect::<HashMap<_, _>>();
+    let deltas = cfg
+        .functions
+        .keys()
+        .map(|&entry| (entry, rb_deltas(&cfg, entry)))
+        .collect::<HashMap<_, _>>();
+    let args = code
+        .iter()
+        .map(|(&entry, members)| {
+            let args = arg_slots(&cfg, members, frames[&entry], &deltas[&entry]);
+            (entry, args)
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut out = String::new();
+    for (&entry, members) in &code {
+        let mut function = Function {
+            cfg: &cfg,
+            args: &args,
+            entry,
+            frame: frames[&entry],
+            deltas: deltas[&entry].clone(),
+            gotos: BTreeSet::new(),
+            lines: Vec::new(),
+        };
+        function.region(members, None, 1);
+        for &start in cfg.functions[&entry].iter().filter(|b| data.contains(b)) {
+            function.lines.push(Line::Label(start));
+            let range = data_range(&cfg, start, program.len());
+            let words = program[range.clone()]
+                .iter()
+                .map(i64::to_string)
+                .collect::<Vec<_>>();
+            let line = format!(
+                "data[{}..{}] = [{}];",
+                range.start,
+                range.end,
+                words.join(", ")
+            );
+            function.emit(1, line);
+        }
+
+        let params = args[&entry]
+            .iter()
+            .map(|s| format!("arg{}", s))
+            .collect::<Vec<_>>();
+        writeln!(out, "fn {}({}) {{", function_name(entry), params.join(", ")).unwrap();
+        for line in &function.lines {
+            match line {
+                Line::Label(addr) if function.gotos.contains(addr) => {
+                    writeln!(out, "label_{}:", addr).unwrap()
+                }
+                Line::Label(_) => {}
+                Line::Code(indent, code) => {
+                    writeln!(out, "{}{}", "    ".repeat(*indent), code).unwrap()
+                }
+            }
+        }
+        writeln!(out, "}}\n").unwrap();
+    }
+    out
+}
+
+/// Blocks from which every path runs into an invalid instruction.
+fn data_blocks(cfg: &Cfg) -> BTreeSet<usize> {
+    let mut data = BTreeSet::new();
+    loop {
+        let before = data.len();
+        for (&start, block) in &cfg.blocks {
+            let dead_end = match block.exit {
+                Exit::Invalid(_) => true,
+                Exit::Fallthrough(_)
+                | Exit::Jump(Some(_))
+                | Exit::Branch { taken: Some(_), .. } => {
+                    block.exit.successors().iter().all(|s| data.contains(s))
+                }
+                _ => false,
+            };
+            if dead_end {
+                data.insert(start);
+            }
+        }
+        if data.len() == before {
+            return data;
+        }
+    }
+}
+
+/// The words a data block covers. One ending at an invalid instruction runs
+/// on to the end of the unreachable range that starts there, if any.
+fn data_range(cfg: &Cfg, start: usize, len: usize) -> Range<usize> {
+    let block = &cfg.blocks[&start];
+    let end = match block.exit {
+        Exit::Invalid(addr) => cfg
+            .unreachable
+            .iter()
+            .find(|r| r.contains(&addr))
+            .map_or(addr + 1, |r| r.end),
+        _ => block.instrs.last().map_or(start, Instruction::next),
+    };
+    start.min(len)..end.min(len)
+}
+
+fn function_name(entry: usize) -> String {
+    if entry == 0 {
+        "main".to_owned()
+    } else {
+        format!("fn_{}", entry)
+    }
+}
+
+/// The first positive `arb` in the function, which sets up its frame.
+fn frame_size(cfg: &Cfg, members: &[usize]) -> i64 {
+    members
+        .iter()
+        .flat_map(|start| cfg.blocks[start].instrs.iter())
+        .find_map(|instr| match (instr.code, instr.operands.first()) {
+            (9, Some(Operand::Immediate(k))) if *k > 0 => Some(*k),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+/// How far the relative base has moved since function entry at the start of
+/// each block, or `None` once it has been moved by a non-constant amount.
+fn rb_deltas(cfg: &Cfg, entry: usize) -> HashMap<usize, Option<i64>> {
+    let mut deltas = HashMap::new();
+    let mut work = vec![(entry, Some(0))];
+    while let Some((start, delta)) = work.pop() {
+        if deltas.contains_key(&start) {
+            continue;
+        }
+        deltas.insert(start, delta);
+        let block = &cfg.blocks[&start];
+        let out = block.instrs.iter().fold(delta, advance);
+        for next in block.exit.local_successors() {
+            work.push((next, out));
+        }
+    }
+    deltas
+}
+
+fn advance(delta: Option<i64>, instr: &Instruction) -> Option<i64> {
+    match (instr.code, instr.operands.first()) {
+        (9, Some(Operand::Immediate(k))) => delta.map(|d| d + k),
+        (9, _) => None,
+        _ => delta,
+    }
+}
+
+/// Operand indices an instruction reads and writes.
+fn reads_writes(instr: &Instruction) -> (&'static [usize], &'static [usize]) {
+    match instr.code {
+        1 | 2 | 7 | 8 => (&[0, 1], &[2]),
+        3 => (&[], &[0]),
+        4 | 9 => (&[0], &[]),
+        5 | 6 => (&[0, 1], &[]),
+        _ => (&[], &[]),
+    }
+}
+
+/// Frame slots `1..frame` read before anything in the function writes them.
+fn arg_slots(
+    cfg: &Cfg,
+    members: &[usize],
+    frame: i64,
+    deltas: &HashMap<usize, Option<i64>>,
+) -> BTreeSet<i64> {
+    let mut args = BTreeSet::new();
+    let mut written = BTreeSet::new();
+    for start in members {
+        let mut delta = deltas[start];
+        for instr in &cfg.blocks[start].instrs {
+            let (reads, writes) = reads_writes(instr);
+            for (n, is_write) in reads
+                .iter()
+                .map(|n| (n, false))
+                .chain(writes.iter().map(|n| (n, true)))
+            {
+                if let (Operand::Relative(v), Some(d)) = (instr.operands[*n], delta) {
+                    let slot = d + v;
+                    if slot > 0 && slot < frame {
+                        if is_write {
+                            written.insert(slot);
+                        } else if !written.contains(&slot) {
+                            args.insert(slot);
+                        }
+                    }
+                }
+            }
+            delta = advance(delta, instr);
+        }
+    }
+    args
+}
+
+impl<'a> Function<'a> {
+    fn emit(&mut self, indent: usize, code: String) {
+        self.lines.push(Line::Code(indent, code));
+    }
+
+    fn goto(&mut self, indent: usize, target: usize) {
+        self.gotos.insert(target);
+        self.emit(indent, format!("goto label_{};", target));
+    }
+
+    fn name(&self, operand: Operand, delta: Option<i64>) -> String {
+        match (operand, delta) {
+            (Operand::Position(a), _) => format!("mem[{}]", a),
+            (Operand::Immediate(v), _) => v.to_string(),
+            (Operand::Relative(v), None) => format!("[rb{:+}]", v),
+            (Operand::Relative(v), Some(d)) => {
+                let slot = d + v;
+                if slot >= self.frame {
+                    format!("out{}", slot - self.frame)
+                } else if slot == 0 {
+                    "ret_addr".to_owned()
+                } else if slot < 0 {
+                    format!("[rb{:+}]", v)
+                } else if self.args[&self.entry].contains(&slot) {
+                    format!("arg{}", slot)
+                } else {
+                    format!("local{}", slot)
+                }
+            }
+        }
+    }
+
+    /// Condition under which a `jnz`/`jz` jumps, or the opposite.
+    fn condition(&self, instr: &Instruction, delta: Option<i64>, taken: bool) -> String {
+        let value = self.name(instr.operands[0], delta);
+        if (instr.code == 5) == taken {
+            value
+        } else {
+            format!("!{}", value)
+        }
+    }
+
+    /// Emits everything in a block except the jump that ends it, returning
+    /// the relative base delta at that jump.
+    fn statements(&mut self, start: usize, indent: usize) -> Option<i64> {
+        let block = &self.cfg.blocks[&start];
+        let mut delta = self.deltas.get(&start).cloned().flatten();
+        let mut body = &block.instrs[..];
+        match block.exit {
+            Exit::Call { .. } => body = &body[..body.len().saturating_sub(2)],
+            Exit::Branch { .. } | Exit::Jump(_) | Exit::Return => {
+                body = &body[..body.len().saturating_sub(1)]
+            }
+            _ => {}
+        }
+
+        for instr in body {
+            let n = |i: usize| self.name(instr.operands[i], delta);
+            let line = match instr.code {
+                1 => match (instr.operands[0], instr.operands[1]) {
+                    (Operand::Immediate(0), _) => Some(format!("{} = {};", n(2), n(1))),
+                    (_, Operand::Immediate(0)) => Some(format!("{} = {};", n(2), n(0))),
+                    (_, Operand::Immediate(b)) if b < 0 => {
+                        Some(format!("{} = {} - {};", n(2), n(0), -b))
+                    }
+                    _ => Some(format!("{} = {} + {};", n(2), n(0), n(1))),
+                },
+                2 => match (instr.operands[0], instr.operands[1]) {
+                    (Operand::Immediate(1), _) => Some(format!("{} = {};", n(2), n(1))),
+                    (_, Operand::Immediate(1)) => Some(format!("{} = {};", n(2), n(0))),
+                    (Operand::Immediate(-1), _) => Some(format!("{} = -{};", n(2), n(1))),
+                    (_, Operand::Immediate(-1)) => Some(format!("{} = -{};", n(2), n(0))),
+                    _ => Some(format!("{} = {} * {};", n(2), n(0), n(1))),
+                },
+                3 => Some(format!("{} = input();", n(0))),
+                4 => Some(format!("output({});", n(0))),
+                7 => Some(format!("{} = {} < {};", n(2), n(0), n(1))),
+                8 => Some(format!("{} = {} == {};", n(2), n(0), n(1))),
+                9 => match instr.operands[0] {
+                    Operand::Immediate(_) => None,
+                    _ => Some(format!("rb += {};", n(0))),
+                },
+                // Only jumps that are never taken are left in a block's body.
+                5 | 6 => Some(format!("// jump at {} never taken", instr.addr)),
+                99 => Some("halt;".to_owned()),
+                code => unreachable!("decoded unknown opcode {}", code),
+            };
+            if let Some(line) = line {
+                self.emit(indent, line);
+            }
+            delta = advance(delta, instr);
+        }
+        delta
+    }
+
+    /// Emits a run of blocks in address order. `follow` is where control goes
+    /// when it runs off the end of the run, so no jump is needed to get there.
+    fn region(&mut self, blocks: &[usize], follow: Option<usize>, indent: usize) {
+        let mut i = 0;
+        while i < blocks.len() {
+            let start = blocks[i];
+            let next = blocks.get(i + 1).cloned().or(follow);
+            self.lines.push(Line::Label(start));
+
+            let closes_loop = |b: &usize| match self.cfg.blocks[b].exit {
+                Exit::Branch { taken, .. } => taken == Some(start),
+                _ => false,
+            };
+            if let Some(j) = (i..blocks.len()).rev().find(|&j| closes_loop(&blocks[j])) {
+                self.do_while(&blocks[i..=j], indent);
+                i = j + 1;
+                continue;
+            }
+
+            let delta = self.statements(start, indent);
+            let block = &self.cfg.blocks[&start];
+            let last = block.instrs.last().cloned();
+            match block.exit {
+                Exit::Branch {
+                    taken: Some(t),
+                    fallthrough,
+                } if next == Some(fallthrough) => {
+                    let last = last.unwrap();
+                    match blocks.iter().position(|&b| b == t).filter(|&k| k > i) {
+                        Some(k) => {
+                            i = self.conditional(blocks, i, k, &last, delta, follow, indent);
+                            continue;
+                        }
+                        None => {
+                            let cond = self.condition(&last, delta, true);
+                            self.emit(indent, format!("if ({}) {{", cond));
+                            self.goto(indent + 1, t);
+                            self.emit(indent, "}".to_owned());
+                        }
+                    }
+                }
+                Exit::Branch { taken, fallthrough } => {
+                    let last = last.unwrap();
+                    let cond = self.condition(&last, delta, true);
+                    self.emit(indent, format!("if ({}) {{", cond));
+                    match taken {
+                        Some(t) => self.goto(indent + 1, t),
+                        None => {
+                            let target = self.name(last.operands[1], delta);
+                            self.emit(indent + 1, format!("goto *{};", target));
+                        }
+                    }
+                    self.emit(indent, "}".to_owned());
+                    if next != Some(fallthrough) {
+                        self.goto(indent, fallthrough);
+                    }
+                }
+                Exit::Fallthrough(t) | Exit::Jump(Some(t)) => {
+                    if next != Some(t) {
+                        self.goto(indent, t);
+                    }
+                }
+                Exit::Jump(None) => {
+                    let last = last.unwrap();
+                    let target = self.name(last.operands[1], delta);
+                    self.emit(indent, format!("goto *{};", target));
+                }
+                Exit::Call { target, ret } => {
+                    let args = (1..=self.args[&target].len())
+                        .map(|n| format!("out{}", n))
+                        .collect::<Vec<_>>();
+                    self.emit(
+                        indent,
+                        format!("{}({});", function_name(target), args.join(", ")),
+                    );
+                    if next != Some(ret) {
+                        self.goto(indent, ret);
+                    }
+                }
+                Exit::Return => self.emit(indent, "return;".to_owned()),
+                Exit::Halt => {}
+                Exit::Invalid(addr) => {
+                    self.emit(indent, format!("// invalid instruction at {}", addr))
+                }
+            }
+            i += 1;
+        }
+    }
+
+    /// `blocks` starts at the loop head and ends with the backward branch.
+    fn do_while(&mut self, blocks: &[usize], indent: usize) {
+        let (&tail, body) = blocks.split_last().unwrap();
+        self.emit(indent, "do {".to_owned());
+        self.region(body, Some(tail), indent + 1);
+        if !body.is_empty() {
+            self.lines.push(Line::Label(tail));
+        }
+        let delta = self.statements(tail, indent + 1);
+        let last = self.cfg.blocks[&tail].instrs.last().cloned().unwrap();
+        let cond = self.condition(&last, delta, true);
+        self.emit(indent, format!("}} while ({});", cond));
+    }
+
+    /// Structures a forward branch from `blocks[i]` over `blocks[i + 1..k]`,
+    /// returning the index to carry on from.
+    #[allow(clippy::too_many_arguments)]
+    fn conditional(
+        &mut self,
+        blocks: &[usize],
+        i: usize,
+        k: usize,
+        branch: &Instruction,
+        delta: Option<i64>,
+        follow: Option<usize>,
+        indent: usize,
+    ) -> usize {
+        let head = blocks[i];
+        let then = &blocks[i + 1..k];
+        let then_exit = then.last().map(|b| self.cfg.blocks[b].exit);
+
+        // The body jumps back to the head: a while loop.
+        if then_exit == Some(Exit::Jump(Some(head))) {
+            let has_statements = self.cfg.blocks[&head].instrs.len() > 1;
+            if has_statements {
+                // The head's statements were already emitted once; repeat
+                // them inside the loop so it reads as a single construct.
+                let emitted = self
+                    .lines
+                    .iter()
+                    .rposition(|l| matches!(l, Line::Label(a) if *a == head))
+                    .unwrap();
+                let head_lines = self.lines.split_off(emitted + 1);
+                self.emit(indent, "loop {".to_owned());
+                for line in head_lines {
+                    if let Line::Code(n, code) = line {
+                        self.emit(n + 1, code);
+                    }
+                }
+                let cond = self.condition(branch, delta, true);
+                self.emit(indent + 1, format!("if ({}) {{", cond));
+                self.emit(indent + 2, "break;".to_owned());
+                self.emit(indent + 1, "}".to_owned());
+            } else {
+                let cond = self.condition(branch, delta, false);
+                self.emit(indent, format!("while ({}) {{", cond));
+            }
+            self.region(then, Some(head), indent + 1);
+            self.emit(indent, "}".to_owned());
+            return k;
+        }
+
+        // The body ends by jumping past a second run of blocks: if/else.
+        if let Some(Exit::Jump(Some(e))) = then_exit {
+            if let Some(m) = blocks.iter().position(|&b| b == e).filter(|&m| m > k) {
+                let cond = self.condition(branch, delta, false);
+                self.emit(indent, format!("if ({}) {{", cond));
+                self.region(then, Some(e), indent + 1);
+                self.emit(indent, "} else {".to_owned());
+                self.region(&blocks[k..m], Some(e), indent + 1);
+                self.emit(indent, "}".to_owned());
+                return m;
+            }
+            if Some(e) == follow {
+                let cond = self.condition(branch, delta, false);
+                self.emit(indent, format!("if ({}) {{", cond));
+                self.region(then, Some(e), indent + 1);
+                self.emit(indent, "} else {".to_owned());
+                self.region(&blocks[k..], follow, indent + 1);
+                self.emit(indent, "}".to_owned());
+                return blocks.len();
+            }
+        }
+
+        let cond = self.condition(branch, delta, false);
+        self.emit(indent, format!("if ({}) {{", cond));
+        self.region(then, Some(blocks[k]), indent + 1);
+        self.emit(indent, "}".to_owned());
+        k
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse;
+    use super::*;
+
+    #[test]
+    fn recovers_do_while() {
+        // Count [20] up to 5, printing it each time round.
+        let program = "1001,20,1,20,4,20,1007,20,5,21,1005,21,0,99";
+        let code = decompile(&parse(program));
+        assert!(
+            code.contains("    do {\n        mem[20] = mem[20] + 1;\n"),
+            "{}",
+            code
+        );
+        assert!(code.contains("    } while (mem[21]);"), "{}", code);
+    }
+
+    #[test]
+    fn recovers_if_else() {
+        // Print 1 if the input is non-zero and 2 otherwise.
+        let program = "3,20,1006,20,11,104,1,1106,0,13,99,104,2,99";
+        let code = decompile(&parse(program));
+        assert!(
+            code.contains(
+                "    if (mem[20]) {\n        output(1);\n    } else {\n        output(2);\n    }"
+            ),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn names_frame_slots() {
+        // main passes 21 to fn_12, which doubles its argument in place.
+        let program = "21101,0,21,1,21101,0,11,0,1105,1,12,99,\
+                       109,2,22102,2,-1,-1,109,-2,2105,1,0";
+        let code = decompile(&parse(program));
+        assert!(
+            code.contains("    out1 = 21;\n    fn_12(out1);\n"),
+            "{}",
+            code
+        );
+        assert!(
+            code.contains("fn fn_12(arg1) {\n    arg1 = 2 * arg1;\n    return;\n}"),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn dumps_data_outside_loops() {
+        // A loop with a jump into data at 15 that is never taken, as [21] is 1.
+        let program = "1001,20,1,20,1006,21,15,1007,20,5,22,1005,22,0,99,77,88,0,0,0,0,1,0";
+        let code = decompile(&parse(program));
+        assert!(
+            code.contains(
+                "    do {\n        mem[20] = mem[20] + 1;\n        if (!mem[21]) {\n            \
+                 goto label_15;\n        }\n        mem[22] = mem[20] < 5;\n    \
+                 } while (mem[22]);\n    halt;\nlabel_15:\n    \
+                 data[15..23] = [77, 88, 0, 0, 0, 0, 1, 0];\n}"
+            ),
+            "{}",
+            code
+        );
+        assert!(!code.contains("invalid"), "{}", code);
+    }
+
+    #[test]
+    fn skips_jumps_never_taken() {
+        // The jump at 0 tests an immediate 0, as a nop patch leaves it.
+        let program = "1105,0,7,104,1,99,0,99";
+        let code = decompile(&parse(program));
+        assert!(
+            code.contains("fn main() {\n    // jump at 0 never taken\n    output(1);\n    halt;\n"),
+            "{}",
+            code
+        );
+        assert_eq!(code.matches("halt;").count(), 1, "{}", code);
+    }
+}
@@ -0,0 +1,112 @@
+//! Decoding of individual instructions for the static analyses, which work on
+//! a program as loaded rather than on a running `IntcodeComputer`.
+
+use super::{OpCode, ParamMode};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Position(i64),
+    Immediate(i64),
+    Relative(i64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub addr: usize,
+    pub code: u32,
+    pub operands: Vec<Operand>,
+}
+
+impl Instruction {
+    /// Decodes the instruction at `addr`, or `None` if the word there is not
+    /// a valid opcode. Operands past the end of the program read as 0.
+    pub fn decode(program: &[i64], addr: usize) -> Option<Instruction> {
+        let word = |a: usize| program.get(a).cloned().unwrap_or(0);
+        let opcode = OpCode::new(word(addr))?;
+
+        let operands = opcode
+            .modes
+            .iter()
+            .enumerate()
+            .map(|(n, mode)| {
+                let value = word(addr + 1 + n);
+                match mode {
+                    ParamMode::Position => Operand::Position(value),
+                    ParamMode::Immediate => Operand::Immediate(value),
+                    ParamMode::Relative => Operand::Relative(value),
+                }
+            })
+            .collect();
+
+        Some(Instruction {
+            addr,
+            code: opcode.code,
+            operands,
+        })
+    }
+
+    /// Number of words the instruction occupies.
+    pub fn size(&self) -> usize {
+        1 + self.operands.len()
+    }
+
+    pub fn next(&self) -> usize {
+        self.addr + self.size()
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self.code {
+            1 => "add",
+            2 => "mul",
+            3 => "in",
+            4 => "out",
+            5 => "jnz",
+            6 => "jz",
+            7 => "lt",
+            8 => "eq",
+            9 => "arb",
+            _ => "hlt",
+        }
+    }
+
+    /// For `jnz`/`jz`, whether the jump is taken regardless of memory:
+    /// `Some(true)` always, `Some(false)` never, `None` if it depends.
+    pub fn always_jumps(&self) -> Option<bool> {
+        match (self.code, self.operands.first()) {
+            (5, Some(Operand::Immediate(v))) => Some(*v != 0),
+            (6, Some(Operand::Immediate(v))) => Some(*v == 0),
+            _ => None,
+        }
+    }
+
+    /// For `add`/`mul` on two immediates, the value written.
+    pub fn constant_result(&self) -> Option<i64> {
+        match (self.code, &self.operands[..]) {
+            (1, [Operand::Immediate(a), Operand::Immediate(b), _]) => a.checked_add(*b),
+            (2, [Operand::Immediate(a), Operand::Immediate(b), _]) => a.checked_mul(*b),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Position(v) => write!(f, "[{}]", v),
+            Operand::Immediate(v) => write!(f, "{}", v),
+            Operand::Relative(v) if *v < 0 => write!(f, "[rb-{}]", -v),
+            Operand::Relative(v) => write!(f, "[rb+{}]", v),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.addr, self.mnemonic())?;
+        for (n, operand) in self.operands.iter().enumerate() {
+            write!(f, "{} {}", if n == 0 { "" } else { "," }, operand)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+//! The example programs published with days 2, 5 and 9. The conformance tests
+//! run them, and the build script compiles every one in `ALL` so that the
+//! transpiled backend is checked against the same programs.
+
+pub const DAY2_ADD: &str = "1,0,0,0,99";
+pub const DAY2_MUL: &str = "2,3,0,3,99";
+pub const DAY2_SQUARE: &str = "2,4,4,5,99,0";
+pub const DAY2_OVERWRITES_HALT: &str = include_str!("../../inputs/day2-test.txt");
+pub const DAY2_WALKTHROUGH: &str = "1,9,10,3,2,3,11,0,99,30,40,50";
+
+pub const DAY5_ECHO: &str = "3,0,4,0,99";
+pub const DAY5_MUL_IMMEDIATE: &str = "1002,4,3,4,33";
+pub const DAY5_NEGATIVE_IMMEDIATE: &str = "1101,100,-1,4,0";
+pub const DAY5_EQUAL_POSITION: &str = "3,9,8,9,10,9,4,9,99,-1,8";
+pub const DAY5_EQUAL_IMMEDIATE: &str = "3,3,1108,-1,8,3,4,3,99";
+pub const DAY5_LESS_POSITION: &str = "3,9,7,9,10,9,4,9,99,-1,8";
+pub const DAY5_LESS_IMMEDIATE: &str = "3,3,1107,-1,8,3,4,3,99";
+pub const DAY5_JUMP_POSITION: &str = "3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9";
+pub const DAY5_JUMP_IMMEDIATE: &str = "3,3,1105,-1,9,1101,0,0,12,4,12,99,1";
+pub const DAY5_COMPARE_WITH_8: &str = "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,\
+                                       1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,\
+                                       999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99";
+
+pub const DAY9_QUINE: &str = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+pub const DAY9_SIXTEEN_DIGITS: &str = include_str!("../../inputs/day9-test.txt");
+pub const DAY9_LARGE_NUMBER: &str = "104,1125899906842624,99";
+
+/// Everything above, for the build script to compile.
+pub const ALL: &[&str] = &[
+    DAY2_ADD,
+    DAY2_MUL,
+    DAY2_SQUARE,
+    DAY2_OVERWRITES_HALT,
+    DAY2_WALKTHROUGH,
+    DAY5_ECHO,
+    DAY5_MUL_IMMEDIATE,
+    DAY5_NEGATIVE_IMMEDIATE,
+    DAY5_EQUAL_POSITION,
+    DAY5_EQUAL_IMMEDIATE,
+    DAY5_LESS_POSITION,
+    DAY5_LESS_IMMEDIATE,
+    DAY5_JUMP_POSITION,
+    DAY5_JUMP_IMMEDIATE,
+    DAY5_COMPARE_WITH_8,
+    DAY9_QUINE,
+    DAY9_SIXTEEN_DIGITS,
+    DAY9_LARGE_NUMBER,
+];
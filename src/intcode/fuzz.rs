@@ -0,0 +1,536 @@
+//! Differential fuzzing of the execution backends. Programs are generated as
+//! instruction lists rather than raw memory so that every opcode and mode is
+//! valid and every jump lands on an instruction boundary, which also lets the
+//! shrinker delete instructions without breaking the jumps around them.
+//!
+//! `reference` is a deliberately naive evaluator written straight from the
+//! puzzle text, so there is always something to diff the interpreter against.
+
+use super::{IntcodeComputer, IntcodeError};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::mpsc::Receiver;
+use std::sync::{mpsc, Arc, Mutex};
+
+const SEED: u64 = 0x2019_1202;
+const CASES: usize = 500;
+const STEP_LIMIT: usize = 10_000;
+const DATA_SLOTS: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Halt {
+    Halted,
+    Error(IntcodeError),
+    StepLimit,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Run {
+    output: Vec<i64>,
+    memory: BTreeMap<usize, i64>,
+    halt: Halt,
+}
+
+type Backend = fn(&[i64], &[i64]) -> Run;
+
+#[cfg(not(feature = "jit"))]
+static BACKENDS: &[(&str, Backend)] = &[("reference", reference), ("interpreter", interpreter)];
+#[cfg(feature = "jit")]
+static BACKENDS: &[(&str, Backend)] = &[
+    ("reference", reference),
+    ("interpreter", interpreter),
+    ("jit", jit),
+];
+
+fn interpreter(program: &[i64], inputs: &[i64]) -> Run {
+    let (mut cpu, rx_output) = load(program, inputs);
+
+    let mut halt = Halt::StepLimit;
+    for _ in 0..STEP_LIMIT {
+        match cpu.step() {
+            Ok(true) => {}
+            Ok(false) => {
+                halt = Halt::Halted;
+                break;
+            }
+            Err(e) => {
+                halt = Halt::Error(e);
+                break;
+            }
+        }
+    }
+
+    Run {
+        output: rx_output.try_iter().collect(),
+        memory: normalize(&cpu.memory),
+        halt,
+    }
+}
+
+#[cfg(feature = "jit")]
+fn jit(program: &[i64], inputs: &[i64]) -> Run {
+    let (mut cpu, rx_output) = load(program, inputs);
+    let halt = match super::jit::run(&mut cpu, STEP_LIMIT as u64) {
+        Ok(true) => Halt::Halted,
+        Ok(false) => Halt::StepLimit,
+        Err(e) => Halt::Error(e),
+    };
+
+    Run {
+        output: rx_output.try_iter().collect(),
+        memory: normalize(&cpu.memory),
+        halt,
+    }
+}
+
+fn load(program: &[i64], inputs: &[i64]) -> (IntcodeComputer, Receiver<i64>) {
+    let (tx_input, rx_input) = mpsc::channel();
+    let (tx_output, rx_output) = mpsc::channel();
+    inputs.iter().for_each(|&v| tx_input.send(v).unwrap());
+    drop(tx_input);
+
+    let program = program
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    let cpu = IntcodeComputer::load(
+        "fuzz",
+        &program,
+        Vec::new(),
+        Arc::new(Mutex::new(rx_input)),
+        Arc::new(Mutex::new(tx_output)),
+        super::Backend::Interpreter,
+    );
+    (cpu, rx_output)
+}
+
+fn reference(program: &[i64], inputs: &[i64]) -> Run {
+    let mut mem: HashMap<usize, i64> = program.iter().cloned().enumerate().collect();
+    let mut inputs = inputs.iter().cloned();
+    let mut output = Vec::new();
+    let mut ip = 0usize;
+    let mut rb = 0i64;
+
+    let mut halt = Halt::StepLimit;
+    for _ in 0..STEP_LIMIT {
+        match reference_step(&mut mem, &mut ip, &mut rb, &mut inputs, &mut output) {
+            Ok(true) => {}
+            Ok(false) => {
+                halt = Halt::Halted;
+                break;
+            }
+            Err(e) => {
+                halt = Halt::Error(e);
+                break;
+            }
+        }
+    }
+
+    Run {
+        output,
+        memory: normalize(&mem),
+        halt,
+    }
+}
+
+fn reference_step(
+    mem: &mut HashMap<usize, i64>,
+    ip: &mut usize,
+    rb: &mut i64,
+    inputs: &mut dyn Iterator<Item = i64>,
+    output: &mut Vec<i64>,
+) -> Result<bool, IntcodeError> {
+    let at = *ip;
+    let peek = |mem: &HashMap<usize, i64>, a: usize| *mem.get(&a).unwrap_or(&0);
+    let word = peek(mem, at);
+    let bad = IntcodeError::BadInstruction {
+        addr: at,
+        value: word,
+    };
+    if word < 0 {
+        return Err(bad);
+    }
+    let arity = match word % 100 {
+        1 | 2 | 7 | 8 => 3,
+        5 | 6 => 2,
+        3 | 4 | 9 => 1,
+        99 => 0,
+        _ => return Err(bad),
+    };
+    let modes = (0..arity)
+        .map(|n| (word / 10i64.pow(n as u32 + 2)) % 10)
+        .collect::<Vec<_>>();
+    if modes.iter().any(|&m| m > 2) {
+        return Err(bad);
+    }
+
+    let checked = |a: i64| {
+        if a < 0 {
+            Err(IntcodeError::NegativeAddress { addr: at, value: a })
+        } else {
+            Ok(a as usize)
+        }
+    };
+    let overflow = IntcodeError::Overflow { addr: at };
+    let load = |mem: &HashMap<usize, i64>, n: usize| -> Result<i64, IntcodeError> {
+        let raw = peek(mem, at + 1 + n);
+        match modes[n] {
+            0 => Ok(peek(mem, checked(raw)?)),
+            1 => Ok(raw),
+            _ => Ok(peek(mem, checked(rb.checked_add(raw).ok_or(overflow)?)?)),
+        }
+    };
+    let target = |mem: &HashMap<usize, i64>, n: usize| -> Result<usize, IntcodeError> {
+        let raw = peek(mem, at + 1 + n);
+        match modes[n] {
+            0 => checked(raw),
+            1 => Err(IntcodeError::ImmediateWrite { addr: at }),
+            _ => checked(rb.checked_add(raw).ok_or(overflow)?),
+        }
+    };
+
+    match word % 100 {
+        1 | 2 | 7 | 8 => {
+            let (a, b) = (load(mem, 0)?, load(mem, 1)?);
+            let dst = target(mem, 2)?;
+            let value = match word % 100 {
+                1 => a.checked_add(b).ok_or(overflow)?,
+                2 => a.checked_mul(b).ok_or(overflow)?,
+                7 => (a < b) as i64,
+                _ => (a == b) as i64,
+            };
+            mem.insert(dst, value);
+            *ip += 4;
+        }
+        3 => {
+            let dst = target(mem, 0)?;
+            let value = inputs
+                .next()
+                .ok_or(IntcodeError::InputClosed { addr: at })?;
+            mem.insert(dst, value);
+            *ip += 2;
+        }
+        4 => {
+            output.push(load(mem, 0)?);
+            *ip += 2;
+        }
+        5 | 6 => {
+            let (cond, dest) = (load(mem, 0)?, load(mem, 1)?);
+            if (cond != 0) == (word % 100 == 5) {
+                *ip = checked(dest)?;
+            } else {
+                *ip += 3;
+            }
+        }
+        9 => {
+            *rb = rb.checked_add(load(mem, 0)?).ok_or(overflow)?;
+            *ip += 2;
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Unset memory reads as zero, so explicit zeros are dropped before comparing.
+fn normalize(memory: &HashMap<usize, i64>) -> BTreeMap<usize, i64> {
+    memory
+        .iter()
+        .filter(|(_, &v)| v != 0)
+        .map(|(&k, &v)| (k, v))
+        .collect()
+}
+
+/// xorshift64*, so that a failing seed reproduces on every machine.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn between(&mut self, lo: i64, hi: i64) -> i64 {
+        lo + (self.next() % (hi - lo + 1) as u64) as i64
+    }
+}
+
+/// Position and relative operands name a slot in the data region placed
+/// after the code; relative ones only hit it while the base is still 0.
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Position(usize),
+    Immediate(i64),
+    Relative(usize),
+}
+
+/// Jump targets are instruction indices, where `instrs.len()` means the halt
+/// that is always appended after the code.
+#[derive(Debug, Clone)]
+enum Instr {
+    Binary {
+        code: i64,
+        a: Operand,
+        b: Operand,
+        dst: Operand,
+    },
+    Input(Operand),
+    Output(Operand),
+    Jump {
+        code: i64,
+        cond: Operand,
+        target: usize,
+    },
+    AdjustBase(Operand),
+    Halt,
+}
+
+#[derive(Debug, Clone)]
+struct Case {
+    instrs: Vec<Instr>,
+    data: Vec<i64>,
+    inputs: Vec<i64>,
+}
+
+impl Operand {
+    fn generate(rng: &mut Rng) -> Operand {
+        match rng.below(3) {
+            0 => Operand::Position(rng.below(DATA_SLOTS)),
+            1 => Operand::Immediate(rng.between(-20, 20)),
+            _ => Operand::Relative(rng.below(DATA_SLOTS)),
+        }
+    }
+
+    /// Writes through an immediate operand are left in at a low rate so the
+    /// error path gets compared too.
+    fn generate_dst(rng: &mut Rng) -> Operand {
+        match rng.below(20) {
+            0 => Operand::Immediate(rng.between(0, 3)),
+            x if x % 2 == 0 => Operand::Position(rng.below(DATA_SLOTS)),
+            _ => Operand::Relative(rng.below(DATA_SLOTS)),
+        }
+    }
+
+    fn encode(self, data_base: usize) -> (i64, i64) {
+        match self {
+            Operand::Position(slot) => (0, (data_base + slot) as i64),
+            Operand::Immediate(v) => (1, v),
+            Operand::Relative(slot) => (2, (data_base + slot) as i64),
+        }
+    }
+}
+
+impl Instr {
+    fn generate(rng: &mut Rng, len: usize) -> Instr {
+        match rng.below(20) {
+            0..=7 => Instr::Binary {
+                code: [1, 2, 7, 8][rng.below(4)],
+                a: Operand::generate(rng),
+                b: Operand::generate(rng),
+                dst: Operand::generate_dst(rng),
+            },
+            8 | 9 => Instr::Input(Operand::generate_dst(rng)),
+            10..=12 => Instr::Output(Operand::generate(rng)),
+            13..=16 => Instr::Jump {
+                code: [5, 6][rng.below(2)],
+                cond: Operand::generate(rng),
+                target: rng.below(len + 1),
+            },
+            17 | 18 => Instr::AdjustBase(Operand::Immediate(rng.between(-3, 3))),
+            _ => Instr::Halt,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Instr::Binary { .. } => 4,
+            Instr::Jump { .. } => 3,
+            Instr::Input(_) | Instr::Output(_) | Instr::AdjustBase(_) => 2,
+            Instr::Halt => 1,
+        }
+    }
+
+    fn operands(&self) -> Vec<Operand> {
+        match self {
+            Instr::Binary { a, b, dst, .. } => vec![*a, *b, *dst],
+            Instr::Jump { cond, .. } => vec![*cond],
+            Instr::Input(op) | Instr::Output(op) | Instr::AdjustBase(op) => vec![*op],
+            Instr::Halt => vec![],
+        }
+    }
+
+    fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Instr::Binary { a, b, dst, .. } => vec![a, b, dst],
+            Instr::Jump { cond, .. } => vec![cond],
+            Instr::Input(op) | Instr::Output(op) | Instr::AdjustBase(op) => vec![op],
+            Instr::Halt => vec![],
+        }
+    }
+}
+
+impl Case {
+    fn generate(rng: &mut Rng) -> Case {
+        let len = 1 + rng.below(24);
+        Case {
+            instrs: (0..len).map(|_| Instr::generate(rng, len)).collect(),
+            data: (0..DATA_SLOTS).map(|_| rng.between(-20, 20)).collect(),
+            inputs: (0..rng.below(8)).map(|_| rng.between(-20, 20)).collect(),
+        }
+    }
+
+    fn encode(&self) -> Vec<i64> {
+        let mut addrs = Vec::new();
+        let mut addr = 0;
+        for instr in &self.instrs {
+            addrs.push(addr);
+            addr += instr.size();
+        }
+        addrs.push(addr);
+        let data_base = addr + 1;
+
+        let mut program = Vec::new();
+        for instr in &self.instrs {
+            let (code, operands) = match instr {
+                Instr::Binary { code, a, b, dst } => (*code, vec![*a, *b, *dst]),
+                Instr::Input(dst) => (3, vec![*dst]),
+                Instr::Output(src) => (4, vec![*src]),
+                Instr::Jump { code, cond, target } => (
+                    *code,
+                    vec![*cond, Operand::Immediate(addrs[*target] as i64)],
+                ),
+                Instr::AdjustBase(val) => (9, vec![*val]),
+                Instr::Halt => (99, vec![]),
+            };
+            let encoded = operands
+                .into_iter()
+                .map(|op| op.encode(data_base))
+                .collect::<Vec<_>>();
+            let modes = encoded
+                .iter()
+                .enumerate()
+                .map(|(n, (mode, _))| mode * 10i64.pow(n as u32 + 2))
+                .sum::<i64>();
+            program.push(code + modes);
+            program.extend(encoded.into_iter().map(|(_, word)| word));
+        }
+        program.push(99);
+        program.extend(&self.data);
+        program
+    }
+
+    /// Every one-step simplification of this case, smallest first.
+    fn shrink_candidates(&self) -> Vec<Case> {
+        let mut candidates = Vec::new();
+
+        for i in 0..self.instrs.len() {
+            let mut case = self.clone();
+            case.instrs.remove(i);
+            for instr in &mut case.instrs {
+                if let Instr::Jump { target, .. } = instr {
+                    if *target > i {
+                        *target -= 1;
+                    }
+                }
+            }
+            candidates.push(case);
+        }
+
+        if !self.inputs.is_empty() {
+            let mut case = self.clone();
+            case.inputs.pop();
+            candidates.push(case);
+        }
+        for i in 0..self.inputs.len() {
+            if self.inputs[i] != 0 {
+                let mut case = self.clone();
+                case.inputs[i] = 0;
+                candidates.push(case);
+            }
+        }
+        for i in 0..self.data.len() {
+            if self.data[i] != 0 {
+                let mut case = self.clone();
+                case.data[i] = 0;
+                candidates.push(case);
+            }
+        }
+        for i in 0..self.instrs.len() {
+            for (n, operand) in self.instrs[i].operands().into_iter().enumerate() {
+                if let Operand::Immediate(v) = operand {
+                    if v != 0 {
+                        let mut case = self.clone();
+                        *case.instrs[i].operands_mut()[n] = Operand::Immediate(0);
+                        candidates.push(case);
+                    }
+                }
+            }
+        }
+
+        candidates
+    }
+}
+
+/// Greedily applies simplifications for as long as `fails` keeps holding.
+fn shrink(mut case: Case, fails: impl Fn(&Case) -> bool) -> Case {
+    'outer: loop {
+        for candidate in case.shrink_candidates() {
+            if fails(&candidate) {
+                case = candidate;
+                continue 'outer;
+            }
+        }
+        return case;
+    }
+}
+
+fn disagreement(case: &Case) -> Option<String> {
+    let program = case.encode();
+    let mut runs = BACKENDS
+        .iter()
+        .map(|(name, backend)| (name, backend(&program, &case.inputs)));
+    let (first_name, first) = runs.next().unwrap();
+
+    runs.find(|(_, run)| *run != first)
+        .map(|(name, run)| format!("{} gave {:?}, {} gave {:?}", first_name, first, name, run))
+}
+
+#[test]
+fn backends_agree_on_random_programs() {
+    let mut rng = Rng(SEED);
+    for _ in 0..CASES {
+        let case = Case::generate(&mut rng);
+        if disagreement(&case).is_some() {
+            let minimal = shrink(case, |c| disagreement(c).is_some());
+            panic!(
+                "Backends disagree on program {:?} with input {:?}: {}",
+                minimal.encode(),
+                minimal.inputs,
+                disagreement(&minimal).unwrap()
+            );
+        }
+    }
+}
+
+#[test]
+fn shrinks_to_minimal_reproducer() {
+    let produces_output =
+        |case: &Case| !interpreter(&case.encode(), &case.inputs).output.is_empty();
+
+    let mut rng = Rng(SEED);
+    let case = (0..)
+        .map(|_| Case::generate(&mut rng))
+        .find(|case| case.instrs.len() > 5 && produces_output(case))
+        .unwrap();
+    let minimal = shrink(case, produces_output);
+
+    assert_eq!(minimal.instrs.len(), 1);
+    assert!(matches!(minimal.instrs[0], Instr::Output(_)));
+    assert!(minimal.inputs.is_empty());
+    assert!(minimal.data.iter().all(|&v| v == 0));
+}
@@ -0,0 +1,788 @@
+//! A JIT backend built on Cranelift, selected with `Backend::Jit` and only
+//! available with the `jit` feature. Basic blocks are compiled to native code
+//! the first time execution reaches them and cached by start address, in a
+//! `Jit` the `IntcodeComputer` keeps for the whole run.
+//!
+//! Compiled code works on a flat copy of memory. Like the transpiler, it bakes
+//! in each instruction's opcode and modes but reads operands from memory, so
+//! only writes to opcode words need guarding. A guarded write is done by the
+//! interpreter, and throws away just the blocks compiled from that word.
+//!
+//! Anything the compiled code doesn't handle itself (halting, errors, input
+//! that hasn't arrived yet, writes past the end of the flat memory) exits the
+//! block before the instruction has any effect, and `IntcodeComputer::step`
+//! runs that one instruction.
+//! Compiled code logs the words it writes, so only those are copied back to
+//! the interpreter's memory first.
+
+use super::disasm::{Instruction, Operand};
+use super::IntcodeError;
+use super::{Backend, IntcodeComputer};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, Block, FuncRef, InstBuilder, MemFlags, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+use std::collections::HashMap;
+use std::fmt;
+use std::mem::{self, offset_of, ManuallyDrop};
+
+/// Longest run of instructions compiled into one block.
+const MAX_BLOCK: usize = 256;
+/// Programs touching memory past this are left to the interpreter.
+const DENSE_LIMIT: usize = 1 << 20;
+/// Invalidated blocks whose code is kept before the module is rebuilt to free
+/// it, since Cranelift can't free single functions.
+const MAX_STALE: usize = 1 << 10;
+
+/// The block ran to its end or took a jump; carry on at `instr_ptr`.
+const CONTINUE: i64 = 0;
+/// The instruction at `instr_ptr` needs the interpreter.
+const BAIL: i64 = 1;
+/// As `BAIL`, for a write to an opcode word of a compiled block.
+const GUARD: i64 = 2;
+/// The instruction budget ran out before the instruction at `instr_ptr`.
+const FUEL: i64 = 3;
+
+/// Everything compiled code reads or updates, passed to every block.
+#[repr(C)]
+struct Context {
+    memory: *mut i64,
+    len: u64,
+    /// One byte per word of memory, non-zero for compiled opcode words.
+    code: *const u8,
+    /// One byte per word of memory, non-zero once it is in `written`.
+    dirty: *mut u8,
+    written: *mut u64,
+    written_len: u64,
+    rel_base: i64,
+    instr_ptr: u64,
+    fuel: u64,
+    /// Where `intcode_input` leaves the value it read.
+    input: i64,
+    cpu: *const IntcodeComputer,
+}
+
+type BlockFn = unsafe extern "C" fn(*mut Context) -> i64;
+
+/// The Cranelift module blocks are compiled into. Dropping it frees their
+/// code.
+struct Compiler {
+    module: ManuallyDrop<JITModule>,
+    input: FuncId,
+    output: FuncId,
+    func_ctx: FunctionBuilderContext,
+}
+
+struct CompiledBlock {
+    run: BlockFn,
+    /// Where each of its instructions starts.
+    opcodes: Vec<usize>,
+}
+
+/// Compiled blocks and the flat memory they run on, kept between calls to
+/// `run`. The interpreter's memory is brought up to date whenever control
+/// leaves compiled code.
+pub struct Jit {
+    compiler: Compiler,
+    blocks: HashMap<usize, CompiledBlock>,
+    /// The blocks compiled from each opcode word.
+    owners: HashMap<usize, Vec<usize>>,
+    /// Blocks invalidated since the module was last rebuilt.
+    stale: usize,
+    memory: Vec<i64>,
+    code: Vec<u8>,
+    dirty: Vec<u8>,
+    /// Words compiled code has written since memory was last copied back,
+    /// each logged once, so it never needs more room than `memory`.
+    written: Vec<u64>,
+    written_len: usize,
+}
+
+/// Runs `cpu` until it halts or `fuel` instructions have executed, returning
+/// whether it halted.
+pub fn run(cpu: &mut IntcodeComputer, fuel: u64) -> Result<bool, IntcodeError> {
+    let mut jit = match cpu.jit.take() {
+        Some(jit) => jit,
+        None => match Jit::new(cpu) {
+            Some(jit) => Box::new(jit),
+            None => return interpret(cpu, fuel),
+        },
+    };
+    let result = jit.run(cpu, fuel);
+    if cpu.backend == Backend::Jit {
+        cpu.jit = Some(jit);
+    }
+    result
+}
+
+fn interpret(cpu: &mut IntcodeComputer, mut fuel: u64) -> Result<bool, IntcodeError> {
+    cpu.backend = Backend::Interpreter;
+    while fuel > 0 && !cpu.cancelled() {
+        fuel -= 1;
+        if !cpu.step()? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// The address the instruction at `instr_ptr` writes to, if it writes.
+fn written_by(cpu: &IntcodeComputer, memory: &[i64]) -> Option<usize> {
+    let instr = Instruction::decode(memory, cpu.instr_ptr)?;
+    let n = match instr.code {
+        1 | 2 | 7 | 8 => 2,
+        3 => 0,
+        _ => return None,
+    };
+    let addr = match instr.operands[n] {
+        Operand::Position(addr) => addr,
+        Operand::Relative(offset) => cpu.rel_base.checked_add(offset)?,
+        Operand::Immediate(_) => return None,
+    };
+    if addr < 0 {
+        None
+    } else {
+        Some(addr as usize)
+    }
+}
+
+extern "C" fn intcode_input(ctx: *mut Context) -> u8 {
+    let ctx = unsafe { &mut *ctx };
+    let cpu = unsafe { &*ctx.cpu };
+    // Waiting is left to the interpreter, which answers control requests
+    // meanwhile from memory brought up to date as compiled code exits.
+    match cpu.input.lock().unwrap().try_recv() {
+        Ok(value) => {
+            ctx.input = value;
+            1
+        }
+        Err(_) => 0,
+    }
+}
+
+extern "C" fn intcode_output(ctx: *mut Context, value: i64) -> u8 {
+    let cpu = unsafe { &*(*ctx).cpu };
+    cpu.output.lock().unwrap().send(value).is_ok() as u8
+}
+
+impl Jit {
+    /// A JIT for `cpu`, or `None` if its memory reaches past `DENSE_LIMIT`.
+    fn new(cpu: &IntcodeComputer) -> Option<Jit> {
+        let used = cpu.memory.keys().max().map_or(0, |&addr| addr + 1);
+        if used > DENSE_LIMIT {
+            return None;
+        }
+        let len = used.next_power_of_two().max(64);
+        let mut memory = vec![0; len];
+        for (&addr, &value) in &cpu.memory {
+            memory[addr] = value;
+        }
+        Some(Jit {
+            compiler: Compiler::new(),
+            blocks: HashMap::new(),
+            owners: HashMap::new(),
+            stale: 0,
+            memory,
+            code: vec![0; len],
+            dirty: vec![0; len],
+            written: vec![0; len],
+            written_len: 0,
+        })
+    }
+
+    fn run(&mut self, cpu: &mut IntcodeComputer, mut fuel: u64) -> Result<bool, IntcodeError> {
+        loop {
+            let block = self.block(cpu.instr_ptr);
+            let mut ctx = Context {
+                memory: self.memory.as_mut_ptr(),
+                len: self.memory.len() as u64,
+                code: self.code.as_ptr(),
+                dirty: self.dirty.as_mut_ptr(),
+                written: self.written.as_mut_ptr(),
+                written_len: self.written_len as u64,
+                rel_base: cpu.rel_base,
+                instr_ptr: cpu.instr_ptr as u64,
+                fuel,
+                input: 0,
+                cpu,
+            };
+            let status = unsafe { block(&mut ctx) };
+            self.written_len = ctx.written_len as usize;
+            cpu.instr_ptr = ctx.instr_ptr as usize;
+            cpu.rel_base = ctx.rel_base;
+            cpu.instructions += fuel - ctx.fuel;
+            fuel = ctx.fuel;
+            if status == CONTINUE {
+                continue;
+            }
+
+            self.write_back(cpu);
+            if status == FUEL || fuel == 0 {
+                return Ok(false);
+            }
+            fuel -= 1;
+            let target = written_by(cpu, &self.memory);
+            if !cpu.step()? {
+                return Ok(true);
+            }
+            if let Some(addr) = target {
+                if addr >= DENSE_LIMIT {
+                    return interpret(cpu, fuel);
+                }
+                self.reload(cpu, addr);
+            }
+            if cpu.cancelled() {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Copies the words compiled code has written to the interpreter's
+    /// memory.
+    fn write_back(&mut self, cpu: &mut IntcodeComputer) {
+        for &addr in &self.written[..self.written_len] {
+            let addr = addr as usize;
+            self.dirty[addr] = 0;
+            cpu.memory.insert(addr, self.memory[addr]);
+        }
+        self.written_len = 0;
+    }
+
+    /// Picks up a word the interpreter may have written, growing memory to
+    /// hold it and dropping any block compiled from it if it changed.
+    fn reload(&mut self, cpu: &IntcodeComputer, addr: usize) {
+        if addr >= self.memory.len() {
+            let len = (addr + 1).next_power_of_two();
+            self.memory.resize(len, 0);
+            self.code.resize(len, 0);
+            self.dirty.resize(len, 0);
+            self.written.resize(len, 0);
+        }
+        let value = cpu.read_addr(addr);
+        if self.code[addr] != 0 && self.memory[addr] != value {
+            self.invalidate(addr);
+        }
+        self.memory[addr] = value;
+    }
+
+    /// Drops every block compiled from the opcode word at `addr`.
+    fn invalidate(&mut self, addr: usize) {
+        for start in self.owners.remove(&addr).unwrap_or_default() {
+            let block = match self.blocks.remove(&start) {
+                Some(block) => block,
+                None => continue,
+            };
+            self.stale += 1;
+            for opcode in block.opcodes {
+                if let Some(owners) = self.owners.get_mut(&opcode) {
+                    owners.retain(|&s| s != start);
+                    if owners.is_empty() {
+                        self.owners.remove(&opcode);
+                        self.code[opcode] = 0;
+                    }
+                }
+            }
+        }
+        self.code[addr] = 0;
+        if self.stale > MAX_STALE {
+            self.flush();
+        }
+    }
+
+    /// Drops every compiled block and frees their code.
+    fn flush(&mut self) {
+        self.compiler = Compiler::new();
+        self.blocks.clear();
+        self.owners.clear();
+        self.code.iter_mut().for_each(|c| *c = 0);
+        self.stale = 0;
+    }
+
+    fn block(&mut self, start: usize) -> BlockFn {
+        if let Some(block) = self.blocks.get(&start) {
+            return block.run;
+        }
+
+        let (instrs, end, status) = decode(&self.memory, start);
+        let run = self.compiler.compile(&instrs, end, status);
+        for instr in &instrs {
+            self.code[instr.addr] = 1;
+            self.owners.entry(instr.addr).or_default().push(start);
+        }
+        let opcodes = instrs.iter().map(|instr| instr.addr).collect();
+        self.blocks.insert(start, CompiledBlock { run, opcodes });
+        run
+    }
+}
+
+impl fmt::Debug for Jit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Jit")
+            .field("blocks", &self.blocks.len())
+            .field("stale", &self.stale)
+            .field("memory", &self.memory.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Compiler {
+    fn new() -> Compiler {
+        let mut flags = settings::builder();
+        flags.set("use_colocated_libcalls", "false").unwrap();
+        flags.set("is_pic", "false").unwrap();
+        flags.set("opt_level", "speed").unwrap();
+        let isa = cranelift_native::builder()
+            .expect("host machine is not supported by Cranelift")
+            .finish(settings::Flags::new(flags))
+            .unwrap();
+
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.symbol("intcode_input", intcode_input as *const u8);
+        builder.symbol("intcode_output", intcode_output as *const u8);
+        let mut module = JITModule::new(builder);
+
+        let ptr = module.target_config().pointer_type();
+        let mut input = module.make_signature();
+        input.params.push(AbiParam::new(ptr));
+        input.returns.push(AbiParam::new(types::I8));
+        let mut output = module.make_signature();
+        output.params.push(AbiParam::new(ptr));
+        output.params.push(AbiParam::new(types::I64));
+        output.returns.push(AbiParam::new(types::I8));
+
+        Compiler {
+            input: module
+                .declare_function("intcode_input", Linkage::Import, &input)
+                .unwrap(),
+            output: module
+                .declare_function("intcode_output", Linkage::Import, &output)
+                .unwrap(),
+            module: ManuallyDrop::new(module),
+            func_ctx: FunctionBuilderContext::new(),
+        }
+    }
+
+    /// Compiles `instrs`, which leave off at `end` with `status`.
+    fn compile(&mut self, instrs: &[Instruction], end: usize, status: i64) -> BlockFn {
+        let ptr = self.module.target_config().pointer_type();
+        let mut ctx = self.module.make_context();
+        ctx.func.signature.params.push(AbiParam::new(ptr));
+        ctx.func.signature.returns.push(AbiParam::new(types::I64));
+        let id = self
+            .module
+            .declare_anonymous_function(&ctx.func.signature)
+            .unwrap();
+
+        let mut b = FunctionBuilder::new(&mut ctx.func, &mut self.func_ctx);
+        let input = self.module.declare_func_in_func(self.input, b.func);
+        let output = self.module.declare_func_in_func(self.output, b.func);
+
+        let entry = b.create_block();
+        b.append_block_params_for_function_params(entry);
+        b.switch_to_block(entry);
+        let context = b.block_params(entry)[0];
+
+        let rel_base = Variable::from_u32(0);
+        let fuel = Variable::from_u32(1);
+        b.declare_var(rel_base, types::I64);
+        b.declare_var(fuel, types::I64);
+        let flags = MemFlags::trusted();
+        let value = b.ins().load(
+            types::I64,
+            flags,
+            context,
+            offset(offset_of!(Context, rel_base)),
+        );
+        b.def_var(rel_base, value);
+        let value = b.ins().load(
+            types::I64,
+            flags,
+            context,
+            offset(offset_of!(Context, fuel)),
+        );
+        b.def_var(fuel, value);
+
+        let exit = b.create_block();
+        b.append_block_param(exit, types::I64);
+        b.append_block_param(exit, types::I64);
+
+        let mut e = Emitter {
+            memory: b
+                .ins()
+                .load(ptr, flags, context, offset(offset_of!(Context, memory))),
+            len: b
+                .ins()
+                .load(types::I64, flags, context, offset(offset_of!(Context, len))),
+            code: b
+                .ins()
+                .load(ptr, flags, context, offset(offset_of!(Context, code))),
+            dirty: b
+                .ins()
+                .load(ptr, flags, context, offset(offset_of!(Context, dirty))),
+            written: b
+                .ins()
+                .load(ptr, flags, context, offset(offset_of!(Context, written))),
+            b,
+            context,
+            rel_base,
+            fuel,
+            exit,
+            input,
+            output,
+        };
+        for instr in instrs {
+            e.instruction(instr);
+        }
+        e.exit(status, end);
+
+        let mut b = e.b;
+        b.switch_to_block(exit);
+        let (status, ip) = (b.block_params(exit)[0], b.block_params(exit)[1]);
+        let rel_base = b.use_var(rel_base);
+        let fuel = b.use_var(fuel);
+        b.ins()
+            .store(flags, ip, context, offset(offset_of!(Context, instr_ptr)));
+        b.ins().store(
+            flags,
+            rel_base,
+            context,
+            offset(offset_of!(Context, rel_base)),
+        );
+        b.ins()
+            .store(flags, fuel, context, offset(offset_of!(Context, fuel)));
+        b.ins().return_(&[status]);
+        b.seal_all_blocks();
+        b.finalize();
+
+        self.module.define_function(id, &mut ctx).unwrap();
+        self.module.clear_context(&mut ctx);
+        self.module.finalize_definitions().unwrap();
+        unsafe { mem::transmute::<*const u8, BlockFn>(self.module.get_finalized_function(id)) }
+    }
+}
+
+impl Drop for Compiler {
+    fn drop(&mut self) {
+        // Compiled blocks are only called from `Jit::run`, which never keeps
+        // one across a flush or past the end of the run.
+        unsafe { ManuallyDrop::take(&mut self.module).free_memory() };
+    }
+}
+
+/// The instructions compiled for a block starting at `start`, where the
+/// block leaves off and how. A block ends after a jump, or before anything
+/// left to the interpreter: a halt, an invalid instruction, a write in
+/// immediate mode, or an instruction running off the end of memory.
+fn decode(memory: &[i64], start: usize) -> (Vec<Instruction>, usize, i64) {
+    let mut instrs = Vec::new();
+    let mut addr = start;
+    while instrs.len() < MAX_BLOCK {
+        let instr = match Instruction::decode(memory, addr) {
+            Some(instr) if instr.next() <= memory.len() && compiles(&instr) => instr,
+            _ => return (instrs, addr, BAIL),
+        };
+        addr = instr.next();
+        let jump = instr.code == 5 || instr.code == 6;
+        instrs.push(instr);
+        if jump {
+            break;
+        }
+    }
+    (instrs, addr, CONTINUE)
+}
+
+fn compiles(instr: &Instruction) -> bool {
+    let written = match instr.code {
+        1 | 2 | 7 | 8 => Some(2),
+        3 => Some(0),
+        99 => return false,
+        _ => None,
+    };
+    !matches!(
+        written.map(|n| instr.operands[n]),
+        Some(Operand::Immediate(_))
+    )
+}
+
+fn offset(field: usize) -> i32 {
+    field as i32
+}
+
+struct Emitter<'a> {
+    b: FunctionBuilder<'a>,
+    context: Value,
+    memory: Value,
+    len: Value,
+    code: Value,
+    dirty: Value,
+    written: Value,
+    rel_base: Variable,
+    fuel: Variable,
+    exit: Block,
+    input: FuncRef,
+    output: FuncRef,
+}
+
+impl Emitter<'_> {
+    fn instruction(&mut self, instr: &Instruction) {
+        let at = instr.addr;
+        let fuel = self.b.use_var(self.fuel);
+        let empty = self.b.ins().icmp_imm(IntCC::Equal, fuel, 0);
+        self.exit_if(empty, FUEL, at);
+        let remaining = self.b.ins().iadd_imm(fuel, -1);
+
+        match instr.code {
+            1 | 2 | 7 | 8 => {
+                let a = self.operand(instr, 0);
+                let b = self.operand(instr, 1);
+                let dst = self.target(instr, 2);
+                let value = match instr.code {
+                    1 | 2 => {
+                        let (value, overflow) = if instr.code == 1 {
+                            self.b.ins().sadd_overflow(a, b)
+                        } else {
+                            self.b.ins().smul_overflow(a, b)
+                        };
+                        self.exit_if(overflow, BAIL, at);
+                        value
+                    }
+                    code => {
+                        let cc = if code == 7 {
+                            IntCC::SignedLessThan
+                        } else {
+                            IntCC::Equal
+                        };
+                        let flag = self.b.ins().icmp(cc, a, b);
+                        self.b.ins().uextend(types::I64, flag)
+                    }
+                };
+                self.store(dst, value);
+            }
+            3 => {
+                let dst = self.target(instr, 0);
+                let call = self.b.ins().call(self.input, &[self.context]);
+                let ok = self.b.inst_results(call)[0];
+                let closed = self.b.ins().icmp_imm(IntCC::Equal, ok, 0);
+                self.exit_if(closed, BAIL, at);
+                let value = self.b.ins().load(
+                    types::I64,
+                    MemFlags::trusted(),
+                    self.context,
+                    offset(offset_of!(Context, input)),
+                );
+                self.store(dst, value);
+            }
+            4 => {
+                let value = self.operand(instr, 0);
+                let call = self.b.ins().call(self.output, &[self.context, value]);
+                let ok = self.b.inst_results(call)[0];
+                let closed = self.b.ins().icmp_imm(IntCC::Equal, ok, 0);
+                self.exit_if(closed, BAIL, at);
+            }
+            5 | 6 => {
+                let cond = self.operand(instr, 0);
+                let dest = self.operand(instr, 1);
+                let cc = if instr.code == 5 {
+                    IntCC::NotEqual
+                } else {
+                    IntCC::Equal
+                };
+                let taken = self.b.ins().icmp_imm(cc, cond, 0);
+                let jump = self.b.create_block();
+                let fallthrough = self.b.create_block();
+                self.b.ins().brif(taken, jump, &[], fallthrough, &[]);
+
+                self.b.switch_to_block(jump);
+                let negative = self.b.ins().icmp_imm(IntCC::SignedLessThan, dest, 0);
+                self.exit_if(negative, BAIL, at);
+                self.b.def_var(self.fuel, remaining);
+                let status = self.b.ins().iconst(types::I64, CONTINUE);
+                self.b.ins().jump(self.exit, &[status, dest]);
+
+                self.b.switch_to_block(fallthrough);
+            }
+            9 => {
+                let value = self.operand(instr, 0);
+                let rel_base = self.relative(at, value);
+                self.b.def_var(self.rel_base, rel_base);
+            }
+            code => unreachable!("opcode {} is never compiled", code),
+        }
+        self.b.def_var(self.fuel, remaining);
+    }
+
+    fn exit(&mut self, status: i64, ip: usize) {
+        let status = self.b.ins().iconst(types::I64, status);
+        let ip = self.b.ins().iconst(types::I64, ip as i64);
+        self.b.ins().jump(self.exit, &[status, ip]);
+    }
+
+    /// Leaves the block with `status` before the instruction at `at` if
+    /// `cond` is set.
+    fn exit_if(&mut self, cond: Value, status: i64, at: usize) {
+        let next = self.b.create_block();
+        let status = self.b.ins().iconst(types::I64, status);
+        let ip = self.b.ins().iconst(types::I64, at as i64);
+        self.b.ins().brif(cond, self.exit, &[status, ip], next, &[]);
+        self.b.switch_to_block(next);
+    }
+
+    /// The word at a fixed address, known to be inside memory.
+    fn word(&mut self, addr: usize) -> Value {
+        self.b.ins().load(
+            types::I64,
+            MemFlags::trusted(),
+            self.memory,
+            (addr * 8) as i32,
+        )
+    }
+
+    fn operand(&mut self, instr: &Instruction, n: usize) -> Value {
+        let word = self.word(instr.addr + 1 + n);
+        match instr.operands[n] {
+            Operand::Immediate(_) => word,
+            Operand::Position(_) => self.read(instr.addr, word),
+            Operand::Relative(_) => {
+                let addr = self.relative(instr.addr, word);
+                self.read(instr.addr, addr)
+            }
+        }
+    }
+
+    /// The address an instruction writes to, checked to be a word of memory
+    /// that isn't compiled code.
+    fn target(&mut self, instr: &Instruction, n: usize) -> Value {
+        let word = self.word(instr.addr + 1 + n);
+        let addr = match instr.operands[n] {
+            Operand::Relative(_) => self.relative(instr.addr, word),
+            _ => word,
+        };
+
+        let outside = self
+            .b
+            .ins()
+            .icmp(IntCC::UnsignedGreaterThanOrEqual, addr, self.len);
+        self.exit_if(outside, BAIL, instr.addr);
+        let flag = self.b.ins().iadd(self.code, addr);
+        let flag = self
+            .b
+            .ins()
+            .uload8(types::I32, MemFlags::trusted(), flag, 0);
+        let guarded = self.b.ins().icmp_imm(IntCC::NotEqual, flag, 0);
+        self.exit_if(guarded, GUARD, instr.addr);
+        addr
+    }
+
+    fn relative(&mut self, at: usize, offset: Value) -> Value {
+        let rel_base = self.b.use_var(self.rel_base);
+        let (addr, overflow) = self.b.ins().sadd_overflow(rel_base, offset);
+        self.exit_if(overflow, BAIL, at);
+        addr
+    }
+
+    /// Loads from a computed address, which reads as 0 past the end of
+    /// memory.
+    fn read(&mut self, at: usize, addr: Value) -> Value {
+        let negative = self.b.ins().icmp_imm(IntCC::SignedLessThan, addr, 0);
+        self.exit_if(negative, BAIL, at);
+
+        let inside = self.b.ins().icmp(IntCC::UnsignedLessThan, addr, self.len);
+        let load = self.b.create_block();
+        let join = self.b.create_block();
+        self.b.append_block_param(join, types::I64);
+        let zero = self.b.ins().iconst(types::I64, 0);
+        self.b.ins().brif(inside, load, &[], join, &[zero]);
+
+        self.b.switch_to_block(load);
+        let value = self.load(addr);
+        self.b.ins().jump(join, &[value]);
+
+        self.b.switch_to_block(join);
+        self.b.block_params(join)[0]
+    }
+
+    fn load(&mut self, addr: Value) -> Value {
+        let byte_offset = self.b.ins().ishl_imm(addr, 3);
+        let p = self.b.ins().iadd(self.memory, byte_offset);
+        self.b.ins().load(types::I64, MemFlags::trusted(), p, 0)
+    }
+
+    /// Stores to a word of memory, logging it in `written` the first time.
+    fn store(&mut self, addr: Value, value: Value) {
+        let flags = MemFlags::trusted();
+        let byte_offset = self.b.ins().ishl_imm(addr, 3);
+        let p = self.b.ins().iadd(self.memory, byte_offset);
+        self.b.ins().store(flags, value, p, 0);
+
+        let dirty = self.b.ins().iadd(self.dirty, addr);
+        let flag = self.b.ins().uload8(types::I32, flags, dirty, 0);
+        let log = self.b.create_block();
+        let done = self.b.create_block();
+        self.b.ins().brif(flag, done, &[], log, &[]);
+
+        self.b.switch_to_block(log);
+        let one = self.b.ins().iconst(types::I8, 1);
+        self.b.ins().store(flags, one, dirty, 0);
+        let len_offset = offset(offset_of!(Context, written_len));
+        let len = self
+            .b
+            .ins()
+            .load(types::I64, flags, self.context, len_offset);
+        let byte_offset = self.b.ins().ishl_imm(len, 3);
+        let p = self.b.ins().iadd(self.written, byte_offset);
+        self.b.ins().store(flags, addr, p, 0);
+        let len = self.b.ins().iadd_imm(len, 1);
+        self.b.ins().store(flags, len, self.context, len_offset);
+        self.b.ins().jump(done, &[]);
+
+        self.b.switch_to_block(done);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Backend, Halt, IntcodeComputer};
+    use std::sync::{mpsc, Arc, Mutex};
+
+    // Adds 5 to [30] until it reaches 100, but turns its own add at 0 into a
+    // mul after every pass: 1 + 5 = 6, 6 * 5 = 30, 30 * 5 = 150.
+    const SELF_MODIFYING: &str = "1001,30,5,30,1007,30,100,31,1006,31,18,1101,1002,0,0,1105,1,0,\
+                                  4,30,99,0,0,0,0,0,0,0,0,0,1,0";
+
+    #[test]
+    fn deoptimizes_when_compiled_opcode_is_overwritten() {
+        let program = SELF_MODIFYING;
+        let jit =
+            IntcodeComputer::execute_with_backend("test", program, Vec::new(), &[], Backend::Jit);
+        let interpreted = IntcodeComputer::execute("test", program, Vec::new(), &[]);
+
+        assert_eq!(jit.result, Ok(()));
+        assert_eq!(jit.output, vec![150]);
+        assert_eq!(jit.output, interpreted.output);
+    }
+
+    #[test]
+    fn keeps_blocks_not_written_to() {
+        let (_tx_input, rx_input) = mpsc::channel();
+        let (tx_output, rx_output) = mpsc::channel();
+        let mut cpu = IntcodeComputer::load(
+            "test",
+            SELF_MODIFYING,
+            Vec::new(),
+            Arc::new(Mutex::new(rx_input)),
+            Arc::new(Mutex::new(tx_output)),
+            Backend::Jit,
+        );
+        assert_eq!(cpu.run(), Halt::Finished);
+        assert_eq!(rx_output.try_recv(), Ok(150));
+
+        // Only the first write of the mul changes the block at 0, and the
+        // block doing the write is never recompiled.
+        let jit = cpu.jit.as_ref().unwrap();
+        assert_eq!(jit.stale, 1);
+        assert!(jit.blocks.contains_key(&0));
+        assert!(jit.blocks.contains_key(&11));
+    }
+}
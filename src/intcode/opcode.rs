@@ -0,0 +1,60 @@
+//! Instruction word decoding, shared by the interpreter and the static
+//! analyses. Kept free of the rest of `intcode` so the build script can
+//! include it alongside the transpiler.
+
+use phf::phf_map;
+
+#[derive(Debug, Clone)]
+pub struct OpCode {
+    pub code: u32,
+    pub modes: Vec<ParamMode>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ParamMode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+pub static OPCODE_SIZE: phf::Map<u32, usize> = phf_map! {
+    1u32 => 3,
+    2u32 => 3,
+    3u32 => 1,
+    4u32 => 1,
+    5u32 => 2,
+    6u32 => 2,
+    7u32 => 3,
+    8u32 => 3,
+    9u32 => 1,
+    99u32 => 0,
+};
+
+impl OpCode {
+    /// Decodes an instruction word, or `None` if the opcode or any of its
+    /// parameter modes is unknown.
+    pub fn new(value: i64) -> Option<OpCode> {
+        if value < 0 {
+            return None;
+        }
+        let code = (value % 100) as u32;
+        let size = *OPCODE_SIZE.get(&code)?;
+
+        let mut modes = Vec::new();
+        let mut codes = value / 100;
+
+        for _ in 0..size {
+            let param_mode = match codes % 10 {
+                0 => ParamMode::Position,
+                1 => ParamMode::Immediate,
+                2 => ParamMode::Relative,
+                _ => return None,
+            };
+
+            modes.push(param_mode);
+            codes /= 10;
+        }
+
+        Some(OpCode { code, modes })
+    }
+}
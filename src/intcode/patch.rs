@@ -0,0 +1,413 @@
+//! Named patches to a program, kept in files next to the puzzle inputs and
+//! applied when the program is loaded. A patch for `inputs/day13.txt` called
+//! `free-play` lives in `inputs/day13-free-play.patch`.
+//!
+//! Each line of a patch is one edit, and every edit states the words it
+//! expects to replace so it fails loudly against the wrong program:
+//!
+//! ```text
+//! # Comments and blank lines are ignored.
+//! set 0: 1 -> 2                 words at an address
+//! nop 12: 1005,20,2             the instructions at an address, made to do nothing
+//! replace 7,8,9 -> 7,0,9        a sequence found exactly once in the program
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+    Set {
+        addr: usize,
+        old: Vec<i64>,
+        new: Vec<i64>,
+    },
+    Nop {
+        addr: usize,
+        old: Vec<i64>,
+    },
+    Replace {
+        old: Vec<i64>,
+        new: Vec<i64>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    pub name: String,
+    /// Each edit with the line it came from.
+    pub edits: Vec<(usize, Edit)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+    Io {
+        path: String,
+        message: String,
+    },
+    Parse {
+        patch: String,
+        line: usize,
+        message: String,
+    },
+    Mismatch {
+        patch: String,
+        line: usize,
+        addr: usize,
+        expected: Vec<i64>,
+        found: Vec<i64>,
+    },
+    NotFound {
+        patch: String,
+        line: usize,
+    },
+    Ambiguous {
+        patch: String,
+        line: usize,
+        addrs: Vec<usize>,
+    },
+}
+
+impl Patch {
+    pub fn parse(name: &str, s: &str) -> Result<Patch, PatchError> {
+        let mut edits = Vec::new();
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| PatchError::Parse {
+                patch: name.to_owned(),
+                line: n + 1,
+                message: message.to_owned(),
+            };
+            let edit = parse_edit(line).map_err(|message| error(&message))?;
+            edits.push((n + 1, edit));
+        }
+
+        Ok(Patch {
+            name: name.to_owned(),
+            edits,
+        })
+    }
+
+    /// Loads a patch file, named after its file stem.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Patch, PatchError> {
+        let path = path.as_ref();
+        let s = fs::read_to_string(path).map_err(|e| PatchError::Io {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        })?;
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        Patch::parse(&name, &s)
+    }
+
+    /// Loads the patch called `name` stored next to `program`.
+    pub fn named<P: AsRef<Path>>(program: P, name: &str) -> Result<Patch, PatchError> {
+        let program = program.as_ref();
+        let stem = program.file_stem().unwrap_or_default().to_string_lossy();
+        let path = program.with_file_name(format!("{}-{}.patch", stem, name));
+        Patch::load(path)
+    }
+
+    /// Loads `patch` from a file if there is one at that path, and otherwise
+    /// the patch of that name stored next to `program`.
+    pub fn locate<P: AsRef<Path>>(program: P, patch: &str) -> Result<Patch, PatchError> {
+        if Path::new(patch).is_file() {
+            Patch::load(patch)
+        } else {
+            Patch::named(program, patch)
+        }
+    }
+
+    /// The overrides that apply this patch when loading `program`, after
+    /// checking that every edit matches it.
+    pub fn overrides(&self, program: &[i64]) -> Result<Vec<(usize, i64)>, PatchError> {
+        self.writes(&mut program.to_vec())
+    }
+
+    /// Patches `program` in place, for tools that work on the words directly.
+    pub fn apply(&self, program: &mut [i64]) -> Result<(), PatchError> {
+        let mut patched = program.to_vec();
+        self.writes(&mut patched)?;
+        program.copy_from_slice(&patched);
+        Ok(())
+    }
+
+    /// Applies the edits in order, so later ones see the earlier ones.
+    fn writes(&self, program: &mut [i64]) -> Result<Vec<(usize, i64)>, PatchError> {
+        let mut writes = Vec::new();
+        for (line, edit) in &self.edits {
+            let (addr, old, new) = match edit {
+                Edit::Set { addr, old, new } => (*addr, old, new.clone()),
+                Edit::Nop { addr, old } => (*addr, old, nops(old.len())),
+                Edit::Replace { old, new } => (self.find(program, *line, old)?, old, new.clone()),
+            };
+
+            let found: Vec<i64> = program
+                .get(addr..)
+                .unwrap_or_default()
+                .iter()
+                .take(old.len())
+                .copied()
+                .collect();
+            if &found != old {
+                return Err(PatchError::Mismatch {
+                    patch: self.name.clone(),
+                    line: *line,
+                    addr,
+                    expected: old.clone(),
+                    found,
+                });
+            }
+
+            for (i, value) in new.into_iter().enumerate() {
+                program[addr + i] = value;
+                writes.push((addr + i, value));
+            }
+        }
+        Ok(writes)
+    }
+
+    fn find(&self, program: &[i64], line: usize, sequence: &[i64]) -> Result<usize, PatchError> {
+        let addrs = program
+            .windows(sequence.len())
+            .enumerate()
+            .filter(|(_, words)| *words == sequence)
+            .map(|(addr, _)| addr)
+            .collect::<Vec<_>>();
+
+        match addrs[..] {
+            [addr] => Ok(addr),
+            [] => Err(PatchError::NotFound {
+                patch: self.name.clone(),
+                line,
+            }),
+            _ => Err(PatchError::Ambiguous {
+                patch: self.name.clone(),
+                line,
+                addrs,
+            }),
+        }
+    }
+}
+
+fn parse_edit(line: &str) -> Result<Edit, String> {
+    let (keyword, rest) = line
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| format!("Expected an edit, got {:?}", line))?;
+    let rest = rest.trim();
+
+    match keyword {
+        "set" => {
+            let (addr, rest) = parse_addr(rest)?;
+            let (old, new) = parse_change(rest)?;
+            Ok(Edit::Set { addr, old, new })
+        }
+        "nop" => {
+            let (addr, rest) = parse_addr(rest)?;
+            let old = parse_words(rest)?;
+            if old.len() < 2 {
+                return Err("Can't nop out fewer than 2 words".to_owned());
+            }
+            Ok(Edit::Nop { addr, old })
+        }
+        "replace" => {
+            let (old, new) = parse_change(rest)?;
+            Ok(Edit::Replace { old, new })
+        }
+        _ => Err(format!("Unknown edit {:?}", keyword)),
+    }
+}
+
+fn parse_addr(s: &str) -> Result<(usize, &str), String> {
+    let (addr, rest) = s
+        .split_once(':')
+        .ok_or_else(|| "Expected an address followed by ':'".to_owned())?;
+    let addr = addr
+        .trim()
+        .parse()
+        .map_err(|_| format!("Bad address {:?}", addr.trim()))?;
+    Ok((addr, rest))
+}
+
+fn parse_change(s: &str) -> Result<(Vec<i64>, Vec<i64>), String> {
+    let (old, new) = s
+        .split_once("->")
+        .ok_or_else(|| "Expected old -> new".to_owned())?;
+    let (old, new) = (parse_words(old)?, parse_words(new)?);
+    if old.len() != new.len() {
+        return Err(format!(
+            "Replacing {} words with {} would move the rest of the program",
+            old.len(),
+            new.len()
+        ));
+    }
+    Ok((old, new))
+}
+
+fn parse_words(s: &str) -> Result<Vec<i64>, String> {
+    s.split(',')
+        .map(|word| {
+            word.trim()
+                .parse()
+                .map_err(|_| format!("Bad word {:?}", word.trim()))
+        })
+        .collect()
+}
+
+/// `len` words that execute without effect: `jnz 0, 0` and `arb 0`.
+fn nops(len: usize) -> Vec<i64> {
+    let mut words = Vec::new();
+    while words.len() < len {
+        match len - words.len() {
+            2 | 4 => words.extend(&[109, 0]),
+            _ => words.extend(&[1105, 0, 0]),
+        }
+    }
+    words
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::Io { path, message } => write!(f, "Can't read patch {}: {}", path, message),
+            PatchError::Parse {
+                patch,
+                line,
+                message,
+            } => write!(f, "Patch {} line {}: {}", patch, line, message),
+            PatchError::Mismatch {
+                patch,
+                line,
+                addr,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Patch {} line {}: expected {:?} at {}, found {:?}",
+                patch, line, expected, addr, found
+            ),
+            PatchError::NotFound { patch, line } => {
+                write!(f, "Patch {} line {}: sequence not found", patch, line)
+            }
+            PatchError::Ambiguous { patch, line, addrs } => write!(
+                f,
+                "Patch {} line {}: sequence found at each of {:?}",
+                patch, line, addrs
+            ),
+        }
+    }
+}
+
+/// The overrides that apply each of `patches` in turn when loading `program`
+/// from `path`, each found with `Patch::locate`.
+pub fn overrides<P: AsRef<Path>>(
+    path: P,
+    program: &[i64],
+    patches: &[String],
+) -> Result<Vec<(usize, i64)>, PatchError> {
+    let mut program = program.to_vec();
+    let mut overrides = Vec::new();
+    for patch in patches {
+        let writes = Patch::locate(&path, patch)?.overrides(&program)?;
+        for &(addr, value) in &writes {
+            program[addr] = value;
+        }
+        overrides.extend(writes);
+    }
+    Ok(overrides)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{parse, IntcodeComputer};
+    use super::*;
+
+    const PROGRAM: &str = "1101,2,3,12,4,12,1105,0,0,4,12,99,0";
+
+    #[test]
+    fn applies_each_kind_of_edit() {
+        let patch = Patch::parse(
+            "test",
+            "# Add different numbers, then skip the second output.\n\
+             set 1: 2,3 -> 5,7\n\
+             \n\
+             nop 9: 4,12\n\
+             replace 1105,0,0 -> 1105,0,11\n",
+        )
+        .unwrap();
+
+        let mut program = parse(PROGRAM);
+        patch.apply(&mut program).unwrap();
+        assert_eq!(program, parse("1101,5,7,12,4,12,1105,0,11,109,0,99,0"));
+
+        let run = IntcodeComputer::execute(
+            "test",
+            PROGRAM,
+            patch.overrides(&parse(PROGRAM)).unwrap(),
+            &[],
+        );
+        assert_eq!(run.output, vec![12]);
+    }
+
+    #[test]
+    fn fails_on_unexpected_words() {
+        let patch = Patch::parse("test", "set 1: 2 -> 5\nset 2: 4 -> 5").unwrap();
+        assert_eq!(
+            patch.overrides(&parse(PROGRAM)),
+            Err(PatchError::Mismatch {
+                patch: "test".to_owned(),
+                line: 2,
+                addr: 2,
+                expected: vec![4],
+                found: vec![3],
+            })
+        );
+    }
+
+    #[test]
+    fn replace_needs_a_unique_match() {
+        let patch = Patch::parse("test", "replace 4,12 -> 4,0").unwrap();
+        assert_eq!(
+            patch.overrides(&parse(PROGRAM)),
+            Err(PatchError::Ambiguous {
+                patch: "test".to_owned(),
+                line: 1,
+                addrs: vec![4, 9],
+            })
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_a_bad_edit() {
+        let error = Patch::parse("test", "# ok\nset 1: 2 -> 5,6").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Patch test line 2: Replacing 1 words with 2 would move the rest of the program"
+        );
+    }
+
+    #[test]
+    fn day13_free_play_matches_input() {
+        let program = parse(include_str!("../../inputs/day13.txt"));
+        let patch = Patch::named("inputs/day13.txt", "free-play").unwrap();
+        assert_eq!(patch.overrides(&program), Ok(vec![(0, 2)]));
+    }
+
+    #[test]
+    fn finds_patches_by_name_or_path() {
+        let path = "inputs/day13.txt";
+        let program = parse(&fs::read_to_string(path).unwrap());
+        let expected = Patch::named(path, "free-play")
+            .and_then(|patch| patch.overrides(&program))
+            .unwrap();
+
+        let by_name = overrides(path, &program, &["free-play".to_owned()]);
+        let by_path = overrides(path, &program, &["inputs/day13-free-play.patch".to_owned()]);
+        assert_eq!(by_name, Ok(expected.clone()));
+        assert_eq!(by_path, Ok(expected));
+        assert_eq!(overrides(path, &program, &[]), Ok(Vec::new()));
+    }
+}
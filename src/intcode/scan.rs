@@ -0,0 +1,110 @@
+//! Finding where a running program keeps some piece of its state. Start from
+//! a snapshot of memory, then at each later pause keep only the addresses
+//! whose values behaved the way the state did, until one is left.
+
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Predicate {
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    Equals(i64),
+}
+
+#[derive(Debug, Clone)]
+pub struct Scanner {
+    /// Each address still in the running, with its value at the last snapshot.
+    candidates: BTreeMap<usize, i64>,
+}
+
+impl Scanner {
+    /// Every address in `memory` is a candidate to begin with.
+    pub fn new(memory: &HashMap<usize, i64>) -> Scanner {
+        Scanner {
+            candidates: memory.iter().map(|(&addr, &value)| (addr, value)).collect(),
+        }
+    }
+
+    /// Drops the candidates whose value going from the last snapshot to
+    /// `memory` doesn't satisfy `predicate`.
+    pub fn narrow(&mut self, memory: &HashMap<usize, i64>, predicate: Predicate) {
+        self.candidates.retain(|addr, old| {
+            let new = *memory.get(addr).unwrap_or(&0);
+            let keep = predicate.holds(*old, new);
+            *old = new;
+            keep
+        });
+    }
+
+    pub fn candidates(&self) -> Vec<usize> {
+        self.candidates.keys().copied().collect()
+    }
+
+    /// The address, once the search is down to one.
+    pub fn found(&self) -> Option<usize> {
+        match self.candidates.len() {
+            1 => self.candidates.keys().next().copied(),
+            _ => None,
+        }
+    }
+}
+
+impl Predicate {
+    fn holds(self, old: i64, new: i64) -> bool {
+        match self {
+            Predicate::Changed => new != old,
+            Predicate::Unchanged => new == old,
+            Predicate::Increased => new > old,
+            Predicate::Decreased => new < old,
+            Predicate::Equals(value) => new == value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::session::{Pause, Session};
+    use super::*;
+
+    // Reads into [11] and counts inputs in [12] until it reads a 0. [10] is
+    // a constant 7.
+    const COUNTER: &str = "3,11,1001,12,1,12,1005,11,0,99,7,0,0";
+
+    fn paused(session: &mut Session, input: i64) -> HashMap<usize, i64> {
+        session.send(input);
+        assert_eq!(session.run(), Ok(Pause::Input));
+        session.memory().clone()
+    }
+
+    #[test]
+    fn narrows_to_the_counter() {
+        let mut session = Session::new("counter", COUNTER, Vec::new());
+        assert_eq!(session.run(), Ok(Pause::Input));
+        let mut scanner = Scanner::new(session.memory());
+
+        scanner.narrow(&paused(&mut session, 5), Predicate::Increased);
+        assert_eq!(scanner.candidates(), vec![11, 12]);
+        scanner.narrow(&paused(&mut session, 3), Predicate::Decreased);
+        assert_eq!(scanner.found(), Some(11));
+
+        let mut scanner = Scanner::new(session.memory());
+        scanner.narrow(&paused(&mut session, 3), Predicate::Changed);
+        assert_eq!(scanner.found(), Some(12));
+    }
+
+    #[test]
+    fn finds_values_and_forces_them() {
+        let mut session = Session::new("counter", COUNTER, Vec::new());
+        assert_eq!(session.run(), Ok(Pause::Input));
+        let mut scanner = Scanner::new(session.memory());
+
+        scanner.narrow(&paused(&mut session, 1), Predicate::Equals(7));
+        scanner.narrow(&paused(&mut session, 1), Predicate::Unchanged);
+        assert_eq!(scanner.found(), Some(10));
+
+        session.write(12, 40);
+        assert_eq!(paused(&mut session, 1)[&12], 41);
+    }
+}
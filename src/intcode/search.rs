@@ -0,0 +1,319 @@
+//! Brute-force searches over the ways a program can be started, spread across
+//! every core. A `Space` lists the candidate starting conditions and `Search`
+//! runs the base program once per candidate, interpreted or compiled.
+
+use super::compiled::Compiled;
+use super::{CancelToken, IntcodeComputer, RunResult};
+use permute::permutations_of;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// One way of starting a program: memory writes applied after loading, and
+/// the values queued on its input before it runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Candidate {
+    pub overrides: Vec<(usize, i64)>,
+    pub inputs: Vec<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Space {
+    candidates: Vec<Candidate>,
+}
+
+#[derive(Debug)]
+pub struct Search<'a> {
+    program: Program<'a>,
+    space: Space,
+}
+
+#[derive(Debug)]
+enum Program<'a> {
+    /// A name for log events and the program's source.
+    Source(&'a str, &'a str),
+    Compiled(&'static Compiled),
+}
+
+impl Space {
+    /// A single candidate writing `overrides`, such as those from a patch,
+    /// for putting in front of every candidate of another space.
+    pub fn fixed(overrides: Vec<(usize, i64)>) -> Space {
+        Space {
+            candidates: vec![Candidate {
+                overrides,
+                inputs: Vec::new(),
+            }],
+        }
+    }
+
+    /// Every value in `values` written to `addr`.
+    pub fn overrides(addr: usize, values: Range<i64>) -> Space {
+        Space {
+            candidates: values
+                .map(|v| Candidate {
+                    overrides: vec![(addr, v)],
+                    inputs: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Every value in `values` as a single initial input.
+    pub fn inputs(values: Range<i64>) -> Space {
+        Space {
+            candidates: values
+                .map(|v| Candidate {
+                    overrides: Vec::new(),
+                    inputs: vec![v],
+                })
+                .collect(),
+        }
+    }
+
+    /// Every ordering of `values` as the initial inputs.
+    pub fn permutations(values: &[i64]) -> Space {
+        Space {
+            candidates: permutations_of(values)
+                .map(|permutation| Candidate {
+                    overrides: Vec::new(),
+                    inputs: permutation.cloned().collect(),
+                })
+                .collect(),
+        }
+    }
+
+    /// The cartesian product of two spaces. Overrides from both sides are
+    /// applied and inputs from `self` are queued before those from `other`.
+    pub fn product(self, other: Space) -> Space {
+        let mut candidates = Vec::new();
+        for a in &self.candidates {
+            for b in &other.candidates {
+                candidates.push(Candidate {
+                    overrides: [&a.overrides[..], &b.overrides[..]].concat(),
+                    inputs: [&a.inputs[..], &b.inputs[..]].concat(),
+                });
+            }
+        }
+        Space { candidates }
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    /// The first candidate, in space order, for which `f` returns something.
+    /// Workers stop picking up new candidates as soon as any match is found.
+    pub fn find_map<T, F>(&self, f: F) -> Option<(Candidate, T)>
+    where
+        T: Send,
+        F: Fn(&Candidate) -> Option<T> + Sync,
+    {
+        self.find_map_cancellable(|c, _| f(c))
+    }
+
+    /// As `find_map`, with `f` handed a token that is cancelled once a match
+    /// earlier in the space makes its answer useless.
+    fn find_map_cancellable<T, F>(&self, f: F) -> Option<(Candidate, T)>
+    where
+        T: Send,
+        F: Fn(&Candidate, &CancelToken) -> Option<T> + Sync,
+    {
+        self.evaluate(f, true)
+            .into_iter()
+            .min_by_key(|(i, _)| *i)
+            .map(|(i, v)| (self.candidates[i].clone(), v))
+    }
+
+    pub fn max_by_key<F>(&self, f: F) -> Option<(Candidate, i64)>
+    where
+        F: Fn(&Candidate) -> i64 + Sync,
+    {
+        self.evaluate(|c, _| Some(f(c)), false)
+            .into_iter()
+            .max_by_key(|&(i, v)| (v, std::cmp::Reverse(i)))
+            .map(|(i, v)| (self.candidates[i].clone(), v))
+    }
+
+    pub fn min_by_key<F>(&self, f: F) -> Option<(Candidate, i64)>
+    where
+        F: Fn(&Candidate) -> i64 + Sync,
+    {
+        self.evaluate(|c, _| Some(f(c)), false)
+            .into_iter()
+            .min_by_key(|&(i, v)| (v, i))
+            .map(|(i, v)| (self.candidates[i].clone(), v))
+    }
+
+    /// Runs `f` over the candidates on a thread per core, handing each a
+    /// token of its own. Candidates are claimed in order, and when
+    /// `stop_early` is set a match cancels only the candidates after it, so
+    /// every candidate before the first match is still evaluated. Whatever a
+    /// cancelled candidate returns is dropped.
+    fn evaluate<T, F>(&self, f: F, stop_early: bool) -> Vec<(usize, T)>
+    where
+        T: Send,
+        F: Fn(&Candidate, &CancelToken) -> Option<T> + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let first_match = AtomicUsize::new(usize::MAX);
+        let running = Mutex::new(HashMap::new());
+        let results = Mutex::new(Vec::new());
+        let workers = thread::available_parallelism().map_or(1, |n| n.get());
+
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    if i >= self.candidates.len() {
+                        break;
+                    }
+                    let cancel = CancelToken::default();
+                    {
+                        // A match updates `first_match` and cancels under
+                        // this lock, so it can't land between the check and
+                        // the insert and miss this candidate.
+                        let mut running = running.lock().unwrap();
+                        if i > first_match.load(Ordering::SeqCst) {
+                            break;
+                        }
+                        running.insert(i, cancel.clone());
+                    }
+                    let result = f(&self.candidates[i], &cancel);
+                    running.lock().unwrap().remove(&i);
+
+                    let v = match result {
+                        Some(v) if !cancel.is_cancelled() => v,
+                        _ => continue,
+                    };
+                    results.lock().unwrap().push((i, v));
+                    if stop_early {
+                        let running = running.lock().unwrap();
+                        first_match.fetch_min(i, Ordering::SeqCst);
+                        for (_, later) in running.iter().filter(|(&j, _)| j > i) {
+                            later.cancel();
+                        }
+                    }
+                });
+            }
+        });
+
+        results.into_inner().unwrap()
+    }
+}
+
+impl<'a> Search<'a> {
+    pub fn new(name: &'a str, program: &'a str, space: Space) -> Search<'a> {
+        Search {
+            program: Program::Source(name, program),
+            space,
+        }
+    }
+
+    /// A search over a program compiled ahead of time by the build script.
+    pub fn compiled(program: &'static Compiled, space: Space) -> Search<'a> {
+        Search {
+            program: Program::Compiled(program),
+            space,
+        }
+    }
+
+    pub fn run(&self, candidate: &Candidate) -> RunResult {
+        self.run_cancellable(candidate, &CancelToken::default())
+    }
+
+    /// As `run`, stopping early once `cancel` is cancelled. Compiled code
+    /// can't be stopped, so always runs to the end.
+    fn run_cancellable(&self, candidate: &Candidate, cancel: &CancelToken) -> RunResult {
+        let overrides = candidate.overrides.clone();
+        let inputs = &candidate.inputs;
+        match self.program {
+            Program::Source(name, s) => {
+                IntcodeComputer::execute_with_cancel(name, s, overrides, inputs, cancel.clone())
+            }
+            Program::Compiled(c) => c.execute(overrides, inputs),
+        }
+    }
+
+    pub fn find<P>(&self, predicate: P) -> Option<Candidate>
+    where
+        P: Fn(&RunResult) -> bool + Sync,
+    {
+        self.space
+            .find_map_cancellable(|c, cancel| {
+                predicate(&self.run_cancellable(c, cancel)).then_some(())
+            })
+            .map(|(candidate, _)| candidate)
+    }
+
+    pub fn max_by_key<F>(&self, f: F) -> Option<(Candidate, i64)>
+    where
+        F: Fn(&RunResult) -> i64 + Sync,
+    {
+        self.space.max_by_key(|c| f(&self.run(c)))
+    }
+
+    pub fn min_by_key<F>(&self, f: F) -> Option<(Candidate, i64)>
+    where
+        F: Fn(&RunResult) -> i64 + Sync,
+    {
+        self.space.min_by_key(|c| f(&self.run(c)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_returns_first_match_in_order() {
+        let space = Space::inputs(0..1000);
+        let (candidate, _) = space
+            .find_map(|c| (c.inputs[0] % 7 == 3).then_some(()))
+            .unwrap();
+        assert_eq!(candidate.inputs, vec![3]);
+    }
+
+    #[test]
+    fn product_combines_overrides_and_inputs() {
+        let space = Space::overrides(1, 0..2).product(Space::inputs(5..7));
+        assert_eq!(space.len(), 4);
+        assert_eq!(
+            space.candidates[3],
+            Candidate {
+                overrides: vec![(1, 1)],
+                inputs: vec![6],
+            }
+        );
+    }
+
+    #[test]
+    fn search_runs_program_per_candidate() {
+        // Adds the immediates at addresses 1 and 2 into address 0.
+        let space = Space::overrides(1, 0..5).product(Space::overrides(2, 0..5));
+        let search = Search::new("test", "1101,0,0,0,99", space);
+        let found = search.find(|run| run.read(0) == 7).unwrap();
+        assert_eq!(found.overrides, vec![(1, 3), (2, 4)]);
+
+        let (best, value) = search.max_by_key(|run| run.read(0)).unwrap();
+        assert_eq!((best.overrides, value), (vec![(1, 4), (2, 4)], 8));
+    }
+
+    #[test]
+    fn find_cancels_runs_after_a_match() {
+        // Outputs 1 and halts for an input of 0, and spins forever otherwise,
+        // so any later candidate missed by the cancelling hangs the test.
+        // Searched repeatedly to give a race between the two a chance.
+        let search = Search::new("test", "3,9,1005,9,2,104,1,99,0,0", Space::inputs(0..64));
+        for _ in 0..200 {
+            let found = search.find(|run| run.output == [1]).unwrap();
+            assert_eq!(found.inputs, vec![0]);
+        }
+    }
+}
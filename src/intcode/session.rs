@@ -0,0 +1,110 @@
+//! Running a program on the current thread and pausing whenever it waits for
+//! input, so a driver can look at or change its memory between inputs.
+
+use super::stack::Backtrace;
+use super::{Backend, IntcodeComputer, IntcodeError};
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+/// Why `Session::run` returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pause {
+    /// The next instruction reads input and none has been sent.
+    Input,
+    Halted,
+}
+
+#[derive(Debug)]
+pub struct Session {
+    cpu: IntcodeComputer,
+    tx_input: Sender<i64>,
+    rx_output: Receiver<i64>,
+    /// Inputs sent that the program hasn't read yet.
+    pending: usize,
+}
+
+impl Session {
+    pub fn new(name: &str, s: &str, overrides: Vec<(usize, i64)>) -> Session {
+        let (tx_input, rx_input) = mpsc::channel();
+        let (tx_output, rx_output) = mpsc::channel();
+        let cpu = IntcodeComputer::load(
+            name,
+            s,
+            overrides,
+            Arc::new(Mutex::new(rx_input)),
+            Arc::new(Mutex::new(tx_output)),
+            Backend::Interpreter,
+        );
+
+        Session {
+            cpu,
+            tx_input,
+            rx_output,
+            pending: 0,
+        }
+    }
+
+    /// Runs until the program needs input that hasn't been sent, or halts.
+    pub fn run(&mut self) -> Result<Pause, IntcodeError> {
+        loop {
+            let reads_input = self.cpu.parse_opcode()?.code == 3;
+            if reads_input && self.pending == 0 {
+                return Ok(Pause::Input);
+            }
+            if !self.cpu.step()? {
+                return Ok(Pause::Halted);
+            }
+            if reads_input {
+                self.pending -= 1;
+            }
+        }
+    }
+
+    pub fn send(&mut self, value: i64) {
+        self.tx_input.send(value).unwrap();
+        self.pending += 1;
+    }
+
+    /// Everything output since the last call.
+    pub fn output(&self) -> Vec<i64> {
+        self.rx_output.try_iter().collect()
+    }
+
+    pub fn memory(&self) -> &HashMap<usize, i64> {
+        &self.cpu.memory
+    }
+
+    pub fn read(&self, location: usize) -> i64 {
+        self.cpu.read_addr(location)
+    }
+
+    pub fn backtrace(&self) -> Backtrace {
+        self.cpu.backtrace()
+    }
+
+    /// Forces a word of memory, as an override would.
+    pub fn write(&mut self, location: usize, value: i64) {
+        self.cpu.write(location, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pauses_until_input_is_sent() {
+        let mut session = Session::new("test", "3,0,4,0,3,0,4,0,99", Vec::new());
+        assert_eq!(session.run(), Ok(Pause::Input));
+
+        session.send(7);
+        assert_eq!(session.run(), Ok(Pause::Input));
+        assert_eq!(session.output(), vec![7]);
+
+        session.send(8);
+        assert_eq!(session.run(), Ok(Pause::Halted));
+        assert_eq!(session.output(), vec![8]);
+        assert_eq!(session.read(0), 8);
+    }
+}
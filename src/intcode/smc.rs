@@ -0,0 +1,211 @@
+//! Detection of self-modifying code. When asked to, the interpreter records
+//! every write to a word that belongs to an instruction, either one it has
+//! already executed or one found by decoding the program as loaded. Either
+//! kind of write makes cached or statically derived views of the program (the
+//! CFG, compiled blocks) potentially stale.
+
+use super::cfg::Cfg;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Writes by one instruction to a word that was part of an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelfModification {
+    /// The instruction that did the write, or `None` for an override applied
+    /// when the program was loaded.
+    pub writer: Option<usize>,
+    pub addr: usize,
+    /// The word before the first of these writes.
+    pub old: i64,
+    /// The word after the last of them.
+    pub new: i64,
+    /// Whether the word had already been executed as part of an instruction,
+    /// rather than only decoded as one, by the time of any of the writes.
+    pub executed: bool,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Tracker {
+    /// The program as loaded, only decoded once a write might need it.
+    program: Vec<i64>,
+    /// Every word of every instruction reachable in the loaded program.
+    decoded: Option<HashSet<usize>>,
+    /// Start address of each instruction executed, with its size.
+    executed: HashMap<usize, usize>,
+    writes: Vec<SelfModification>,
+    /// Where in `writes` each writer's writes to each address are counted.
+    seen: HashMap<(Option<usize>, usize), usize>,
+}
+
+impl Tracker {
+    pub fn new(program: Vec<i64>) -> Tracker {
+        Tracker {
+            program,
+            ..Tracker::default()
+        }
+    }
+
+    pub fn execute(&mut self, addr: usize, size: usize) {
+        self.executed.insert(addr, size);
+    }
+
+    pub fn write(&mut self, writer: Option<usize>, addr: usize, old: i64, new: i64) {
+        let executed = self.was_executed(addr);
+        if !executed && !self.decoded().contains(&addr) {
+            return;
+        }
+        match self.seen.get(&(writer, addr)) {
+            Some(&i) => {
+                let write = &mut self.writes[i];
+                write.new = new;
+                write.executed |= executed;
+                write.count += 1;
+            }
+            None => {
+                self.seen.insert((writer, addr), self.writes.len());
+                self.writes.push(SelfModification {
+                    writer,
+                    addr,
+                    old,
+                    new,
+                    executed,
+                    count: 1,
+                });
+            }
+        }
+    }
+
+    /// The writes seen, in the order each writer first wrote each address.
+    pub fn into_writes(self) -> Vec<SelfModification> {
+        self.writes
+    }
+
+    fn decoded(&mut self) -> &HashSet<usize> {
+        let program = &self.program;
+        self.decoded.get_or_insert_with(|| {
+            Cfg::build(program)
+                .blocks
+                .values()
+                .flat_map(|block| &block.instrs)
+                .flat_map(|instr| instr.addr..instr.next())
+                .collect()
+        })
+    }
+
+    /// Whether `addr` is inside an executed instruction. Instructions are at
+    /// most four words, so only the starts just before it need checking.
+    fn was_executed(&self, addr: usize) -> bool {
+        (0..4).any(|back| {
+            addr.checked_sub(back)
+                .and_then(|start| self.executed.get(&start))
+                .is_some_and(|&size| back < size)
+        })
+    }
+}
+
+impl fmt::Display for SelfModification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.writer {
+            Some(writer) => write!(f, "{}: ", writer)?,
+            None => write!(f, "override: ")?,
+        }
+        let kind = if self.executed { "executed" } else { "decoded" };
+        write!(f, "[{}] {} -> {} ({})", self.addr, self.old, self.new, kind)?;
+        if self.count > 1 {
+            write!(f, " x{}", self.count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::IntcodeComputer;
+    use super::*;
+
+    #[test]
+    fn reports_day2_overrides_as_decoded_code() {
+        let run = IntcodeComputer::execute_with_smc_tracking(
+            "test",
+            "1,0,0,3,99",
+            vec![(1, 4), (2, 4)],
+            &[],
+        );
+        let overrides = &run.self_modifications[..2];
+        assert_eq!(
+            overrides,
+            &[
+                SelfModification {
+                    writer: None,
+                    addr: 1,
+                    old: 0,
+                    new: 4,
+                    executed: false,
+                    count: 1,
+                },
+                SelfModification {
+                    writer: None,
+                    addr: 2,
+                    old: 0,
+                    new: 4,
+                    executed: false,
+                    count: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_writes_to_executed_instructions() {
+        // Adds [0] to itself over its own destination operand, then halts.
+        let run = IntcodeComputer::execute_with_smc_tracking("test", "1,0,0,3,99", Vec::new(), &[]);
+        assert_eq!(
+            run.self_modifications,
+            vec![SelfModification {
+                writer: Some(0),
+                addr: 3,
+                old: 3,
+                new: 2,
+                executed: true,
+                count: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn ignores_writes_to_data() {
+        let run =
+            IntcodeComputer::execute_with_smc_tracking("test", "1101,2,3,5,99,0", Vec::new(), &[]);
+        assert!(run.self_modifications.is_empty());
+    }
+
+    #[test]
+    fn counts_repeated_writes_once() {
+        // Counts up to 3 in the immediate operand at 5, looping while it's
+        // below 3.
+        let program = "1001,5,1,5,1107,0,3,12,1005,12,0,99,0";
+        let run = IntcodeComputer::execute_with_smc_tracking("test", program, Vec::new(), &[]);
+        assert_eq!(
+            run.self_modifications,
+            vec![SelfModification {
+                writer: Some(0),
+                addr: 5,
+                old: 0,
+                new: 3,
+                executed: true,
+                count: 3,
+            }]
+        );
+        assert_eq!(
+            run.self_modifications[0].to_string(),
+            "0: [5] 0 -> 3 (executed) x3"
+        );
+    }
+
+    #[test]
+    fn only_tracks_when_asked() {
+        let run = IntcodeComputer::execute("test", "1,0,0,3,99", Vec::new(), &[]);
+        assert!(run.self_modifications.is_empty());
+    }
+}
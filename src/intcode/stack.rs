@@ -0,0 +1,173 @@
+//! Best-effort recovery of the call stack of a running program. Intcode has
+//! no call instruction, so a call is recognised the way compiled Intcode
+//! makes one: the caller stores the address after its jump at the relative
+//! base, and the jump lands on an `arb` moving the relative base up past it to
+//! open the callee's frame. The frame closes when the relative base moves back
+//! down to where it was, or when a jump goes to the return address.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// The jump that made the call.
+    pub call_site: usize,
+    /// The start of the function called.
+    pub entry: usize,
+    /// The relative base before and after the frame was opened.
+    pub caller_base: i64,
+    pub base: i64,
+    /// The words from `caller_base` up to `base` when the backtrace was taken:
+    /// the return address, then the arguments the caller stored.
+    pub slots: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+    /// The last jump taken, as (from, to), until an `arb` pairs with it.
+    last_jump: Option<(usize, usize)>,
+}
+
+/// The call stack at some instruction, innermost frame first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backtrace {
+    pub instr_ptr: usize,
+    pub frames: Vec<Frame>,
+}
+
+impl CallStack {
+    pub fn jump(&mut self, from: usize, to: usize) {
+        if let Some(i) = self.frames.iter().rposition(|f| f.return_addr() == to) {
+            self.frames.truncate(i);
+        }
+        self.last_jump = Some((from, to));
+    }
+
+    /// Records an `arb` at `at` moving the relative base from `old` to `new`,
+    /// with memory read through `read`.
+    pub fn arb<F: Fn(usize) -> i64>(&mut self, at: usize, old: i64, new: i64, read: F) {
+        match self.last_jump.take() {
+            Some((from, to))
+                if to == at && old >= 0 && new > old && read(old as usize) == from as i64 + 3 =>
+            {
+                self.frames.push(Frame {
+                    call_site: from,
+                    entry: to,
+                    caller_base: old,
+                    base: new,
+                    slots: Vec::new(),
+                })
+            }
+            _ => {
+                while self.frames.last().is_some_and(|f| new <= f.caller_base) {
+                    self.frames.pop();
+                }
+            }
+        }
+    }
+
+    /// The frames open at `instr_ptr`, with their slots read through `read`.
+    pub fn backtrace<F: Fn(usize) -> i64>(&self, instr_ptr: usize, read: F) -> Backtrace {
+        let frames = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| Frame {
+                slots: (frame.caller_base..frame.base)
+                    .map(|addr| read(addr as usize))
+                    .collect(),
+                ..frame.clone()
+            })
+            .collect();
+
+        Backtrace { instr_ptr, frames }
+    }
+}
+
+impl Frame {
+    /// Where the function returns to: the instruction after the call, since
+    /// both jumps are three words long.
+    pub fn return_addr(&self) -> usize {
+        self.call_site + 3
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Backtrace at {}:", self.instr_ptr)?;
+        if self.frames.is_empty() {
+            write!(f, " no calls")?;
+        }
+        for (i, frame) in self.frames.iter().enumerate() {
+            let slots = frame
+                .slots
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            write!(
+                f,
+                "\n  #{} in {} called from {}, frame [{}..{}): {}",
+                i, frame.entry, frame.call_site, frame.caller_base, frame.base, slots
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::session::Session;
+    use super::*;
+
+    // Sets up a stack at 100, stores the return address 13 and an argument 42
+    // there and calls 14, which opens a frame of two words, reads input and
+    // returns.
+    const CALL: &str = "109,100,21101,0,13,0,21101,0,42,1,1105,1,14,99,109,2,3,200,109,-2,2106,0,0";
+
+    #[test]
+    fn recovers_frame_of_call() {
+        let mut session = Session::new("call", CALL, Vec::new());
+        session.run().unwrap();
+        let backtrace = session.backtrace();
+
+        assert_eq!(
+            backtrace,
+            Backtrace {
+                instr_ptr: 16,
+                frames: vec![Frame {
+                    call_site: 10,
+                    entry: 14,
+                    caller_base: 100,
+                    base: 102,
+                    slots: vec![13, 42],
+                }],
+            }
+        );
+        assert_eq!(
+            backtrace.to_string(),
+            "Backtrace at 16:\n  #0 in 14 called from 10, frame [100..102): 13, 42"
+        );
+    }
+
+    #[test]
+    fn closes_frame_on_return() {
+        let mut session = Session::new("call", CALL, Vec::new());
+        session.run().unwrap();
+        session.send(1);
+        session.run().unwrap();
+        assert_eq!(session.backtrace().frames, Vec::new());
+    }
+
+    #[test]
+    fn ignores_arb_that_is_not_a_call() {
+        let mut stack = CallStack::default();
+        // Not at the target of the last jump.
+        stack.jump(2, 7);
+        stack.arb(9, 0, 5, |_| 5);
+        // Setting up a stack, with no return address stored.
+        stack.jump(8, 12);
+        stack.arb(12, 5, 100, |addr| addr as i64);
+        assert_eq!(stack.backtrace(14, |_| 0).frames, Vec::new());
+    }
+}
@@ -0,0 +1,428 @@
+//! Symbolic execution of straight-line Intcode. Chosen memory cells and inputs
+//! hold named variables instead of numbers, and `add`/`mul`/`lt`/`eq` build up
+//! expressions over them. Anything that needs a concrete value to carry on,
+//! such as a branch condition or a write address, stops the run with a
+//! `SymbolicError` naming the expression that got in the way.
+
+use super::{parse, IntcodeError, OpCode, ParamMode, OPCODE_SIZE};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Mul,
+    Lt,
+    Eq,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Const(i64),
+    Var(String),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    /// A read through a pointer that depends on a variable, resolved against
+    /// the memory as it was when the read happened.
+    Load {
+        addr: Box<Expr>,
+        memory: Arc<HashMap<usize, Expr>>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SymbolicError {
+    Concrete(IntcodeError),
+    Branch { addr: usize, condition: Expr },
+    SymbolicJump { addr: usize, dest: Expr },
+    Write { addr: usize, target: Expr },
+    Instruction { addr: usize, value: Expr },
+    RelativeBase { addr: usize, offset: Expr },
+    InputExhausted { addr: usize },
+}
+
+#[derive(Debug)]
+pub struct SymbolicComputer {
+    memory: HashMap<usize, Expr>,
+    instr_ptr: usize,
+    rel_base: i64,
+    inputs: VecDeque<Expr>,
+    output: Vec<Expr>,
+}
+
+impl Expr {
+    /// Builds `op(a, b)`, folding it away when both sides are known. Returns
+    /// `None` if folding overflows.
+    pub fn binary(op: Op, a: Expr, b: Expr) -> Option<Expr> {
+        match (&a, &b) {
+            (Expr::Const(x), Expr::Const(y)) => op.apply(*x, *y).map(Expr::Const),
+            (Expr::Const(0), _) if op == Op::Add => Some(b),
+            (_, Expr::Const(0)) if op == Op::Add => Some(a),
+            (Expr::Const(0), _) | (_, Expr::Const(0)) if op == Op::Mul => Some(Expr::Const(0)),
+            (Expr::Const(1), _) if op == Op::Mul => Some(b),
+            (_, Expr::Const(1)) if op == Op::Mul => Some(a),
+            _ => Some(Expr::Binary(op, Box::new(a), Box::new(b))),
+        }
+    }
+
+    pub fn as_const(&self) -> Option<i64> {
+        match self {
+            Expr::Const(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Evaluates the expression, or `None` if a variable is unbound, a
+    /// pointer is negative or the arithmetic overflows.
+    pub fn eval(&self, vars: &HashMap<String, i64>) -> Option<i64> {
+        match self {
+            Expr::Const(v) => Some(*v),
+            Expr::Var(name) => vars.get(name).cloned(),
+            Expr::Binary(op, a, b) => op.apply(a.eval(vars)?, b.eval(vars)?),
+            Expr::Load { addr, memory } => {
+                let addr = addr.eval(vars)?;
+                if addr < 0 {
+                    return None;
+                }
+                memory
+                    .get(&(addr as usize))
+                    .map_or(Some(0), |e| e.eval(vars))
+            }
+        }
+    }
+
+    /// The first assignment, trying variables in the order given, that makes
+    /// the expression evaluate to `target`.
+    pub fn solve(
+        &self,
+        target: i64,
+        bounds: &[(&str, Range<i64>)],
+    ) -> Option<HashMap<String, i64>> {
+        let mut vars = HashMap::new();
+        if self.search(target, bounds, &mut vars) {
+            Some(vars)
+        } else {
+            None
+        }
+    }
+
+    fn search(
+        &self,
+        target: i64,
+        bounds: &[(&str, Range<i64>)],
+        vars: &mut HashMap<String, i64>,
+    ) -> bool {
+        match bounds.split_first() {
+            None => self.eval(vars) == Some(target),
+            Some(((name, range), rest)) => {
+                for v in range.clone() {
+                    vars.insert(name.to_string(), v);
+                    if self.search(target, rest, vars) {
+                        return true;
+                    }
+                }
+                vars.remove(*name);
+                false
+            }
+        }
+    }
+}
+
+impl Op {
+    fn apply(self, a: i64, b: i64) -> Option<i64> {
+        match self {
+            Op::Add => a.checked_add(b),
+            Op::Mul => a.checked_mul(b),
+            Op::Lt => Some((a < b) as i64),
+            Op::Eq => Some((a == b) as i64),
+        }
+    }
+}
+
+impl SymbolicComputer {
+    pub fn new(s: &str) -> SymbolicComputer {
+        let memory = parse(s).into_iter().map(Expr::Const).enumerate().collect();
+
+        SymbolicComputer {
+            memory,
+            instr_ptr: 0,
+            rel_base: 0,
+            inputs: VecDeque::new(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn write(&mut self, location: usize, value: i64) {
+        self.memory.insert(location, Expr::Const(value));
+    }
+
+    /// Replaces the value at `location` with the variable `name`.
+    pub fn symbolic_cell(&mut self, location: usize, name: &str) {
+        self.memory.insert(location, Expr::Var(name.to_owned()));
+    }
+
+    pub fn input(&mut self, value: i64) {
+        self.inputs.push_back(Expr::Const(value));
+    }
+
+    /// Queues the variable `name` as the next input.
+    pub fn symbolic_input(&mut self, name: &str) {
+        self.inputs.push_back(Expr::Var(name.to_owned()));
+    }
+
+    pub fn read(&self, location: usize) -> Expr {
+        self.memory
+            .get(&location)
+            .cloned()
+            .unwrap_or(Expr::Const(0))
+    }
+
+    pub fn output(&self) -> &[Expr] {
+        &self.output
+    }
+
+    pub fn run(&mut self) -> Result<(), SymbolicError> {
+        while self.step()? {}
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<bool, SymbolicError> {
+        let at = self.instr_ptr;
+        let word = self.read(at);
+        let word = word.as_const().ok_or(SymbolicError::Instruction {
+            addr: at,
+            value: word,
+        })?;
+        let opcode =
+            OpCode::new(word).ok_or(SymbolicError::Concrete(IntcodeError::BadInstruction {
+                addr: at,
+                value: word,
+            }))?;
+        let modes = &opcode.modes;
+        let next = at + *OPCODE_SIZE.get(&opcode.code).unwrap() + 1;
+
+        match opcode.code {
+            1 | 2 | 7 | 8 => {
+                let a = self.param(0, modes[0])?;
+                let b = self.param(1, modes[1])?;
+                let dst = self.target(2, modes[2])?;
+                let op = match opcode.code {
+                    1 => Op::Add,
+                    2 => Op::Mul,
+                    7 => Op::Lt,
+                    _ => Op::Eq,
+                };
+                let value = Expr::binary(op, a, b)
+                    .ok_or(SymbolicError::Concrete(IntcodeError::Overflow { addr: at }))?;
+                self.memory.insert(dst, value);
+                self.instr_ptr = next;
+            }
+            3 => {
+                let dst = self.target(0, modes[0])?;
+                let value = self
+                    .inputs
+                    .pop_front()
+                    .ok_or(SymbolicError::InputExhausted { addr: at })?;
+                self.memory.insert(dst, value);
+                self.instr_ptr = next;
+            }
+            4 => {
+                let value = self.param(0, modes[0])?;
+                self.output.push(value);
+                self.instr_ptr = next;
+            }
+            5 | 6 => {
+                let condition = self.param(0, modes[0])?;
+                let dest = self.param(1, modes[1])?;
+                let cond = condition.as_const().ok_or(SymbolicError::Branch {
+                    addr: at,
+                    condition,
+                })?;
+                if (cond != 0) == (opcode.code == 5) {
+                    let dest = dest
+                        .as_const()
+                        .ok_or(SymbolicError::SymbolicJump { addr: at, dest })?;
+                    self.instr_ptr = self.addr(dest)?;
+                } else {
+                    self.instr_ptr = next;
+                }
+            }
+            9 => {
+                let offset = self.param(0, modes[0])?;
+                let offset = offset
+                    .as_const()
+                    .ok_or(SymbolicError::RelativeBase { addr: at, offset })?;
+                self.rel_base = self
+                    .rel_base
+                    .checked_add(offset)
+                    .ok_or(SymbolicError::Concrete(IntcodeError::Overflow { addr: at }))?;
+                self.instr_ptr = next;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn param(&self, n: usize, mode: ParamMode) -> Result<Expr, SymbolicError> {
+        let raw = self.read(self.instr_ptr + 1 + n);
+        let pointer = match mode {
+            ParamMode::Immediate => return Ok(raw),
+            ParamMode::Position => raw,
+            ParamMode::Relative => Expr::binary(Op::Add, Expr::Const(self.rel_base), raw).ok_or(
+                SymbolicError::Concrete(IntcodeError::Overflow {
+                    addr: self.instr_ptr,
+                }),
+            )?,
+        };
+
+        match pointer.as_const() {
+            Some(addr) => Ok(self.read(self.addr(addr)?)),
+            None => Ok(Expr::Load {
+                addr: Box::new(pointer),
+                memory: Arc::new(self.memory.clone()),
+            }),
+        }
+    }
+
+    fn target(&self, n: usize, mode: ParamMode) -> Result<usize, SymbolicError> {
+        let raw = self.read(self.instr_ptr + 1 + n);
+        let target = match mode {
+            ParamMode::Immediate => {
+                return Err(SymbolicError::Concrete(IntcodeError::ImmediateWrite {
+                    addr: self.instr_ptr,
+                }))
+            }
+            ParamMode::Position => raw,
+            ParamMode::Relative => Expr::binary(Op::Add, Expr::Const(self.rel_base), raw).ok_or(
+                SymbolicError::Concrete(IntcodeError::Overflow {
+                    addr: self.instr_ptr,
+                }),
+            )?,
+        };
+
+        match target.as_const() {
+            Some(addr) => self.addr(addr),
+            None => Err(SymbolicError::Write {
+                addr: self.instr_ptr,
+                target,
+            }),
+        }
+    }
+
+    fn addr(&self, value: i64) -> Result<usize, SymbolicError> {
+        if value < 0 {
+            Err(SymbolicError::Concrete(IntcodeError::NegativeAddress {
+                addr: self.instr_ptr,
+                value,
+            }))
+        } else {
+            Ok(value as usize)
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Const(v) => write!(f, "{}", v),
+            Expr::Var(name) => write!(f, "{}", name),
+            Expr::Binary(op, a, b) => {
+                let symbol = match op {
+                    Op::Add => "+",
+                    Op::Mul => "*",
+                    Op::Lt => "<",
+                    Op::Eq => "==",
+                };
+                write!(f, "({} {} {})", a, symbol, b)
+            }
+            Expr::Load { addr, .. } => write!(f, "mem[{}]", addr),
+        }
+    }
+}
+
+impl fmt::Display for SymbolicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolicError::Concrete(e) => write!(f, "{}", e),
+            SymbolicError::Branch { addr, condition } => write!(
+                f,
+                "Branch at {} depends on symbolic value {}",
+                addr, condition
+            ),
+            SymbolicError::SymbolicJump { addr, dest } => {
+                write!(f, "Jump at {} goes to symbolic address {}", addr, dest)
+            }
+            SymbolicError::Write { addr, target } => {
+                write!(f, "Write at {} goes to symbolic address {}", addr, target)
+            }
+            SymbolicError::Instruction { addr, value } => {
+                write!(f, "Instruction at {} is symbolic value {}", addr, value)
+            }
+            SymbolicError::RelativeBase { addr, offset } => write!(
+                f,
+                "Relative base adjusted at {} by symbolic value {}",
+                addr, offset
+            ),
+            SymbolicError::InputExhausted { addr } => {
+                write!(f, "Ran out of input while reading at {}", addr)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solves_day2_without_running_it() {
+        let mut cpu = SymbolicComputer::new(include_str!("../../inputs/day2.txt"));
+        cpu.symbolic_cell(1, "noun");
+        cpu.symbolic_cell(2, "verb");
+        cpu.run().unwrap();
+        let expr = cpu.read(0);
+
+        let vars = [("noun".to_owned(), 12), ("verb".to_owned(), 2)];
+        assert_eq!(expr.eval(&vars.iter().cloned().collect()), Some(6568671));
+
+        let solution = expr
+            .solve(19690720, &[("noun", 0..100), ("verb", 0..100)])
+            .unwrap();
+        assert_eq!(100 * solution["noun"] + solution["verb"], 3951);
+    }
+
+    #[test]
+    fn builds_expressions_from_inputs() {
+        let mut cpu = SymbolicComputer::new("3,0,1002,0,3,0,101,4,0,0,4,0,99");
+        cpu.symbolic_input("x");
+        cpu.run().unwrap();
+        assert_eq!(cpu.output()[0].to_string(), "(4 + (x * 3))");
+    }
+
+    #[test]
+    fn reports_branch_on_symbolic_value() {
+        let mut cpu = SymbolicComputer::new("3,3,1105,-1,9,1101,0,0,12,4,12,99,1");
+        cpu.symbolic_input("x");
+        assert_eq!(
+            cpu.run(),
+            Err(SymbolicError::Branch {
+                addr: 2,
+                condition: Expr::Var("x".to_owned()),
+            })
+        );
+    }
+
+    #[test]
+    fn reports_jump_to_symbolic_address() {
+        let mut cpu = SymbolicComputer::new("3,4,1105,1,-1,99");
+        cpu.symbolic_input("x");
+        assert_eq!(
+            cpu.run(),
+            Err(SymbolicError::SymbolicJump {
+                addr: 2,
+                dest: Expr::Var("x".to_owned()),
+            })
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! Ahead-of-time translation of an Intcode program into Rust, for the build
+//! script to write out and `compiled` to include. The generated function is a
+//! loop around one `match` on the instruction pointer with an arm per basic
+//! block of the recovered control-flow graph.
+//!
+//! Only each instruction's opcode and parameter modes are baked into the
+//! generated code. Operands are still read from memory, so a program whose
+//! operands are overwritten before or while it runs stays on the compiled
+//! path; a write that changes an opcode word, or a jump to an address that is
+//! not the start of a block, hands the rest of the run to the interpreter.
+
+use super::cfg::{Cfg, Exit};
+use super::disasm::{Instruction, Operand};
+use std::fmt::Write;
+
+/// Rust source for a `Compiled` static called `name` running `program`.
+pub fn transpile(program: &[i64], name: &str) -> String {
+    let cfg = Cfg::build(program);
+    let body = format!("{}_body", name.to_lowercase());
+
+    let mut opcodes = vec![false; program.len()];
+    for instr in cfg.blocks.values().flat_map(|b| &b.instrs) {
+        if let Some(op) = opcodes.get_mut(instr.addr) {
+            *op = true;
+        }
+    }
+
+    let mut out = String::new();
+    writeln!(out, "pub static {}: Compiled = Compiled {{", name).unwrap();
+    writeln!(out, "    name: {:?},", name.to_lowercase()).unwrap();
+    writeln!(out, "    program: &{:?},", program).unwrap();
+    writeln!(out, "    opcodes: &{:?},", opcodes).unwrap();
+    writeln!(out, "    body: {},", body).unwrap();
+    writeln!(out, "}};").unwrap();
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "fn {}(m: &mut Machine) -> Result<(), IntcodeError> {{",
+        body
+    )
+    .unwrap();
+    writeln!(out, "    'dispatch: loop {{").unwrap();
+    writeln!(out, "        if m.dirty {{").unwrap();
+    writeln!(out, "            return m.interpret();").unwrap();
+    writeln!(out, "        }}").unwrap();
+    writeln!(out, "        match m.instr_ptr {{").unwrap();
+
+    for block in cfg.blocks.values() {
+        writeln!(out, "            {} => {{", block.start).unwrap();
+        let (last, body) = match block.exit {
+            Exit::Fallthrough(_) | Exit::Invalid(_) => (None, &block.instrs[..]),
+            _ => {
+                let (last, body) = block.instrs.split_last().unwrap();
+                (Some(last), body)
+            }
+        };
+        for instr in body {
+            writeln!(out, "                // {}", instr).unwrap();
+            emit(&mut out, instr);
+        }
+        if let Some(last) = last {
+            writeln!(out, "                // {}", last).unwrap();
+        }
+        match (block.exit, last) {
+            (Exit::Fallthrough(next), _) => {
+                writeln!(out, "                m.instr_ptr = {};", next).unwrap()
+            }
+            (Exit::Invalid(addr), _) => {
+                writeln!(out, "                m.instr_ptr = {};", addr).unwrap();
+                writeln!(out, "                return m.interpret();").unwrap();
+            }
+            (Exit::Halt, Some(last)) => {
+                writeln!(out, "                m.instr_ptr = {};", last.addr).unwrap();
+                writeln!(out, "                return Ok(());").unwrap();
+            }
+            (_, Some(last)) => {
+                emit_jump(&mut out, last);
+                writeln!(out, "                m.instr_ptr = {};", last.next()).unwrap();
+            }
+            (_, None) => unreachable!("jump exits always end in an instruction"),
+        }
+        writeln!(out, "            }}").unwrap();
+    }
+
+    writeln!(out, "            _ => return m.interpret(),").unwrap();
+    writeln!(out, "        }}").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+/// Straight-line code for an instruction that does not end its block. Any
+/// write that touches an opcode word leaves the block, since the rest of it
+/// may no longer be what was compiled.
+fn emit(out: &mut String, instr: &Instruction) {
+    let at = instr.addr;
+    let next = instr.next();
+    let read = |n: usize| read(instr, n);
+    let target = |n: usize| target(instr, n);
+    let store = format!(
+        "if m.store(dst, value) {{ m.instr_ptr = {}; continue 'dispatch; }}",
+        next
+    );
+
+    let code = match instr.code {
+        1 | 2 | 7 | 8 => {
+            let value = match instr.code {
+                1 => format!("m.add({}, a, b)?", at),
+                2 => format!("m.mul({}, a, b)?", at),
+                7 => "(a < b) as i64".to_owned(),
+                _ => "(a == b) as i64".to_owned(),
+            };
+            format!(
+                "let a = {}; let b = {}; let dst = {}; let value = {}; {}",
+                read(0),
+                read(1),
+                target(2),
+                value,
+                store
+            )
+        }
+        3 => format!(
+            "let dst = {}; let value = m.input({})?; {}",
+            target(0),
+            at,
+            store
+        ),
+        4 => format!("m.output({}, {})?;", at, read(0)),
+        9 => format!("m.arb({}, {})?;", at, read(0)),
+        5 | 6 => {
+            // A jump that cannot be taken with the program as loaded, which
+            // may still be taken once its operands are overwritten.
+            let taken = if instr.code == 5 { "!=" } else { "==" };
+            format!(
+                "let cond = {}; let dest = {}; \
+                 if cond {} 0 {{ m.instr_ptr = m.jump({}, dest)?; continue 'dispatch; }}",
+                read(0),
+                read(1),
+                taken,
+                at
+            )
+        }
+        code => unreachable!("opcode {} ends its block", code),
+    };
+    writeln!(out, "                {{ {} }}", code).unwrap();
+}
+
+/// The jump that ends a block. Falls through to whatever follows the block
+/// when not taken.
+fn emit_jump(out: &mut String, instr: &Instruction) {
+    let taken = if instr.code == 5 { "!=" } else { "==" };
+    writeln!(out, "                let cond = {};", read(instr, 0)).unwrap();
+    writeln!(out, "                let dest = {};", read(instr, 1)).unwrap();
+    writeln!(out, "                if cond {} 0 {{", taken).unwrap();
+    writeln!(
+        out,
+        "                    m.instr_ptr = m.jump({}, dest)?;",
+        instr.addr
+    )
+    .unwrap();
+    writeln!(out, "                    continue 'dispatch;").unwrap();
+    writeln!(out, "                }}").unwrap();
+}
+
+fn read(instr: &Instruction, n: usize) -> String {
+    let word = instr.addr + 1 + n;
+    match instr.operands[n] {
+        Operand::Position(_) => format!("m.load({}, m.word({}))?", instr.addr, word),
+        Operand::Immediate(_) => format!("m.word({})", word),
+        Operand::Relative(_) => format!("m.load_rel({}, m.word({}))?", instr.addr, word),
+    }
+}
+
+fn target(instr: &Instruction, n: usize) -> String {
+    let word = instr.addr + 1 + n;
+    match instr.operands[n] {
+        Operand::Position(_) => format!("m.target({}, m.word({}))?", instr.addr, word),
+        Operand::Immediate(_) => format!(
+            "Err::<usize, _>(IntcodeError::ImmediateWrite {{ addr: {} }})?",
+            instr.addr
+        ),
+        Operand::Relative(_) => format!(
+            "m.addr({0}, m.offset({0}, m.word({1}))?)?",
+            instr.addr, word
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse;
+    use super::*;
+
+    #[test]
+    fn emits_an_arm_per_block() {
+        let source = transpile(&parse("3,20,1005,20,2,99"), "SPIN");
+
+        assert!(source.starts_with("pub static SPIN: Compiled"));
+        assert!(source.contains("    name: \"spin\","));
+        assert!(source.contains("fn spin_body(m: &mut Machine)"));
+        assert!(source.contains("            0 => {"));
+        assert!(source.contains("            2 => {"));
+        assert!(source.contains("            5 => {"));
+        assert!(source.contains("opcodes: &[true, false, true, false, false, true],"));
+    }
+
+    #[test]
+    fn reads_operands_from_memory() {
+        // Day 2 overwrites these operands before running, so they must not be
+        // baked into the generated code.
+        let source = transpile(&parse("1,0,0,0,99"), "ADD");
+        assert!(source.contains("let a = m.load(0, m.word(1))?; let b = m.load(0, m.word(2))?;"));
+    }
+
+    #[test]
+    fn writes_relative_and_rejects_immediate_targets() {
+        let source = transpile(&parse("21101,1,2,3,11101,1,2,3,99"), "WRITES");
+        assert!(source.contains("m.addr(0, m.offset(0, m.word(3))?)?"));
+        assert!(source.contains("Err::<usize, _>(IntcodeError::ImmediateWrite { addr: 4 })?"));
+    }
+}
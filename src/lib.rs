@@ -0,0 +1,3 @@
+pub mod common;
+pub mod intcode;
+pub mod pathfinding;
@@ -1,8 +1,88 @@
+mod day11;
 mod day13;
-mod intcode;
-mod common;
-//mod robot;
+mod day2;
+mod day7;
+mod robot;
+
+use advent2019::{common, intcode};
+use intcode::cfg::Cfg;
+use intcode::decompile::decompile;
+use intcode::patch::{self, Patch};
+use intcode::IntcodeComputer;
+use log::LevelFilter;
+use std::env;
+use std::fs;
 
 fn main() {
-    day13::day13();
+    let mut args = env::args().collect::<Vec<_>>();
+    let patches = take_patches(&mut args);
+    env_logger::Builder::new()
+        .filter_level(take_verbosity(&mut args))
+        .parse_default_env()
+        .init();
+    match args.get(1).map(String::as_str) {
+        Some("2") => day2::day2(&patches),
+        Some("7") => day7::day7(&patches),
+        Some("11") => day11::day11(&patches),
+        Some("cfg") => {
+            let input = fs::read_to_string(&args[2]).unwrap();
+            let program = patched(&args[2], &input, &patches);
+            print!("{}", Cfg::build(&program).to_dot());
+        }
+        Some("decompile") => {
+            let input = fs::read_to_string(&args[2]).unwrap();
+            print!("{}", decompile(&patched(&args[2], &input, &patches)));
+        }
+        Some("smc") => {
+            let input = fs::read_to_string(&args[2]).unwrap();
+            let mut overrides = patch::overrides(&args[2], &intcode::parse(&input), &patches)
+                .unwrap_or_else(|e| panic!("{}", e));
+            overrides.extend(args[3..].iter().map(|arg| {
+                let (addr, value) = arg.split_once('=').expect("Override as addr=value");
+                (addr.parse::<usize>().unwrap(), value.parse::<i64>().unwrap())
+            }));
+            let run = IntcodeComputer::execute_with_smc_tracking(&args[2], &input, overrides, &[]);
+            run.self_modifications.iter().for_each(|m| println!("{}", m));
+            if let Err(e) = run.result {
+                println!("{}", e);
+            }
+        }
+        _ => day13::day13(&patches),
+    }
+}
+
+/// Removes every `--patch <patch>` from `args`. A patch is either a path to a
+/// patch file or the name of one stored next to the program.
+fn take_patches(args: &mut Vec<String>) -> Vec<String> {
+    let mut patches = Vec::new();
+    while let Some(i) = args.iter().position(|arg| arg == "--patch") {
+        args.remove(i);
+        assert!(i < args.len(), "--patch needs a patch file or name");
+        patches.push(args.remove(i));
+    }
+    patches
+}
+
+/// Removes every `-v` from `args`, returning the log level they ask for:
+/// warnings only by default, then info, debug and trace. `RUST_LOG` overrides
+/// it.
+fn take_verbosity(args: &mut Vec<String>) -> LevelFilter {
+    let before = args.len();
+    args.retain(|arg| arg != "-v");
+    match before - args.len() {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+fn patched(path: &str, input: &str, patches: &[String]) -> Vec<i64> {
+    let mut program = intcode::parse(input);
+    for patch in patches {
+        Patch::locate(path, patch)
+            .and_then(|patch| patch.apply(&mut program))
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+    program
 }
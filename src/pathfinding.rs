@@ -0,0 +1,368 @@
+//! Shortest paths over any state space given as a successor function: BFS
+//! for unit steps, Dijkstra for weighted ones and A* with a heuristic, plus
+//! wrappers for walking a grid. Searches may start from several states at
+//! once, and keep each state's parent so the path to it can be rebuilt.
+
+use super::common::{Cells, Pos};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::hash::Hash;
+
+/// The cost of reaching each state a search got to, and how.
+#[derive(Debug, Clone)]
+pub struct Distances<S> {
+    dist: HashMap<S, u64>,
+    parent: HashMap<S, S>,
+}
+
+impl<S: Hash + Eq + Clone> Distances<S> {
+    fn new<I: IntoIterator<Item = S>>(starts: I) -> Distances<S> {
+        Distances {
+            dist: starts.into_iter().map(|s| (s, 0)).collect(),
+            parent: HashMap::new(),
+        }
+    }
+
+    /// Records reaching `to` from `from` at `cost`, if that's cheaper than
+    /// any way found before.
+    fn relax(&mut self, from: &S, to: S, cost: u64) -> bool {
+        if self.dist.get(&to).is_some_and(|&d| d <= cost) {
+            return false;
+        }
+        self.dist.insert(to.clone(), cost);
+        self.parent.insert(to, from.clone());
+        true
+    }
+
+    pub fn get(&self, state: &S) -> Option<u64> {
+        self.dist.get(state).copied()
+    }
+
+    /// The states reached, with their costs, in no particular order.
+    pub fn reached(&self) -> impl Iterator<Item = (&S, u64)> {
+        self.dist.iter().map(|(s, &d)| (s, d))
+    }
+
+    /// A state reached at the greatest cost, such as the last place oxygen
+    /// spreads to.
+    pub fn farthest(&self) -> Option<(&S, u64)> {
+        self.reached().max_by_key(|&(_, d)| d)
+    }
+
+    /// The cheapest path found from one of the starts to `state`, both ends
+    /// included.
+    pub fn path_to(&self, state: &S) -> Option<Vec<S>> {
+        if !self.dist.contains_key(state) {
+            return None;
+        }
+        let mut path = vec![state.clone()];
+        while let Some(parent) = self.parent.get(path.last().unwrap()) {
+            path.push(parent.clone());
+        }
+        path.reverse();
+        Some(path)
+    }
+}
+
+/// The fewest steps from any of `starts` to every state reachable.
+pub fn bfs<S, I, F>(starts: impl IntoIterator<Item = S>, successors: F) -> Distances<S>
+where
+    S: Hash + Eq + Clone,
+    I: IntoIterator<Item = S>,
+    F: FnMut(&S) -> I,
+{
+    breadth_first(starts, successors, |_| false).0
+}
+
+/// A path of fewest steps from `start` to a state satisfying `goal`.
+pub fn bfs_path<S, I, F, G>(start: S, successors: F, goal: G) -> Option<Vec<S>>
+where
+    S: Hash + Eq + Clone,
+    I: IntoIterator<Item = S>,
+    F: FnMut(&S) -> I,
+    G: Fn(&S) -> bool,
+{
+    let (distances, found) = breadth_first(Some(start), successors, goal);
+    distances.path_to(&found?)
+}
+
+/// The cheapest cost from any of `starts` to every state reachable, with
+/// `successors` giving each next state with the cost of stepping to it.
+pub fn dijkstra<S, I, F>(starts: impl IntoIterator<Item = S>, successors: F) -> Distances<S>
+where
+    S: Hash + Eq + Clone,
+    I: IntoIterator<Item = (S, u64)>,
+    F: FnMut(&S) -> I,
+{
+    best_first(starts, successors, |_| 0, |_| false).0
+}
+
+/// A cheapest path from `start` to a state satisfying `goal`, and its cost.
+pub fn dijkstra_path<S, I, F, G>(start: S, successors: F, goal: G) -> Option<(Vec<S>, u64)>
+where
+    S: Hash + Eq + Clone,
+    I: IntoIterator<Item = (S, u64)>,
+    F: FnMut(&S) -> I,
+    G: Fn(&S) -> bool,
+{
+    astar(start, successors, |_| 0, goal)
+}
+
+/// As `dijkstra_path`, exploring first the states `heuristic` guesses are
+/// nearest the goal. The path is only the cheapest if the heuristic never
+/// overestimates.
+pub fn astar<S, I, F, H, G>(start: S, successors: F, heuristic: H, goal: G) -> Option<(Vec<S>, u64)>
+where
+    S: Hash + Eq + Clone,
+    I: IntoIterator<Item = (S, u64)>,
+    F: FnMut(&S) -> I,
+    H: Fn(&S) -> u64,
+    G: Fn(&S) -> bool,
+{
+    let (distances, found) = best_first(Some(start), successors, heuristic, goal);
+    let found = found?;
+    let cost = distances.get(&found)?;
+    Some((distances.path_to(&found)?, cost))
+}
+
+/// The fewest steps from any of `starts` to every position reachable through
+/// `passable` cells.
+pub fn grid_distances<T, G, P>(
+    grid: &G,
+    starts: impl IntoIterator<Item = Pos>,
+    passable: P,
+) -> Distances<Pos>
+where
+    G: Cells<T>,
+    P: Fn(&T) -> bool,
+{
+    bfs(starts, |pos| open_neighbors(grid, *pos, &passable))
+}
+
+/// A path of fewest steps from `start` to `goal` through `passable` cells.
+pub fn grid_path<T, G, P>(grid: &G, start: Pos, goal: Pos, passable: P) -> Option<Vec<Pos>>
+where
+    G: Cells<T>,
+    P: Fn(&T) -> bool,
+{
+    let steps = |pos: &Pos| open_neighbors(grid, *pos, &passable).map(|next| (next, 1));
+    astar(
+        start,
+        steps,
+        |pos| pos.manhattan(goal) as u64,
+        |pos| *pos == goal,
+    )
+    .map(|(path, _)| path)
+}
+
+fn open_neighbors<'a, T, G, P>(
+    grid: &'a G,
+    pos: Pos,
+    passable: &'a P,
+) -> impl Iterator<Item = Pos> + 'a
+where
+    G: Cells<T>,
+    P: Fn(&T) -> bool,
+{
+    pos.neighbors4()
+        .filter(move |&next| grid.cell(next).is_some_and(passable))
+}
+
+/// Searches in order of steps taken, stopping at the first state satisfying
+/// `goal`, which is returned too.
+fn breadth_first<S, I, F, G>(
+    starts: impl IntoIterator<Item = S>,
+    mut successors: F,
+    goal: G,
+) -> (Distances<S>, Option<S>)
+where
+    S: Hash + Eq + Clone,
+    I: IntoIterator<Item = S>,
+    F: FnMut(&S) -> I,
+    G: Fn(&S) -> bool,
+{
+    let mut queue: VecDeque<S> = starts.into_iter().collect();
+    let mut distances = Distances::new(queue.iter().cloned());
+
+    while let Some(state) = queue.pop_front() {
+        if goal(&state) {
+            return (distances, Some(state));
+        }
+        let cost = distances.dist[&state] + 1;
+        for next in successors(&state) {
+            if distances.relax(&state, next.clone(), cost) {
+                queue.push_back(next);
+            }
+        }
+    }
+    (distances, None)
+}
+
+/// Searches in order of cost so far plus `heuristic`, stopping at the first
+/// state satisfying `goal`, which is returned too.
+fn best_first<S, I, F, H, G>(
+    starts: impl IntoIterator<Item = S>,
+    mut successors: F,
+    heuristic: H,
+    goal: G,
+) -> (Distances<S>, Option<S>)
+where
+    S: Hash + Eq + Clone,
+    I: IntoIterator<Item = (S, u64)>,
+    F: FnMut(&S) -> I,
+    H: Fn(&S) -> u64,
+    G: Fn(&S) -> bool,
+{
+    // States go in `queued` so the heap needn't order them, and an entry is
+    // stale if its state has been reached more cheaply since it was pushed.
+    let mut queued: Vec<(S, u64)> = starts.into_iter().map(|s| (s, 0)).collect();
+    let mut distances = Distances::new(queued.iter().map(|(s, _)| s.clone()));
+    let mut heap: BinaryHeap<_> = queued
+        .iter()
+        .enumerate()
+        .map(|(i, (s, _))| Reverse((heuristic(s), i)))
+        .collect();
+
+    while let Some(Reverse((_, i))) = heap.pop() {
+        let (state, cost) = queued[i].clone();
+        if distances.dist[&state] < cost {
+            continue;
+        }
+        if goal(&state) {
+            return (distances, Some(state));
+        }
+        for (next, step) in successors(&state) {
+            let next_cost = cost + step;
+            if distances.relax(&state, next.clone(), next_cost) {
+                heap.push(Reverse((next_cost + heuristic(&next), queued.len())));
+                queued.push((next, next_cost));
+            }
+        }
+    }
+    (distances, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::common::{Grid, Grid2D, Tile};
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Tile)]
+    enum Cell {
+        #[tile('.')]
+        Open,
+        #[tile('#')]
+        Wall,
+    }
+
+    const MAZE: &str = "\
+#########
+#...#...#
+#.#.#.#.#
+#.#...#.#
+#.#####.#
+#.......#
+#########";
+
+    fn open(cell: &Cell) -> bool {
+        *cell == Cell::Open
+    }
+
+    #[test]
+    fn bfs_counts_steps() {
+        let grid: Grid2D<Cell> = MAZE.parse().unwrap();
+        let distances = grid_distances(&grid, Some(Pos::new(1, 1)), open);
+        assert_eq!(distances.get(&Pos::new(3, 3)), Some(4));
+        assert_eq!(distances.get(&Pos::new(7, 1)), Some(10));
+        assert_eq!(distances.get(&Pos::new(0, 0)), None);
+        assert_eq!(distances.farthest(), Some((&Pos::new(7, 3), 12)));
+
+        let path = distances.path_to(&Pos::new(3, 3)).unwrap();
+        assert_eq!(path.first(), Some(&Pos::new(1, 1)));
+        assert_eq!(path.last(), Some(&Pos::new(3, 3)));
+        assert_eq!(path.len(), 5);
+    }
+
+    #[test]
+    fn bfs_spreads_from_every_start() {
+        let grid = Grid::parse(MAZE, Cell::Wall).unwrap();
+        let starts = vec![Pos::new(1, 1), Pos::new(7, 1)];
+        let distances = grid_distances(&grid, starts, open);
+        assert_eq!(distances.get(&Pos::new(5, 1)), Some(2));
+        assert_eq!(distances.farthest(), Some((&Pos::new(4, 5), 7)));
+        assert_eq!(
+            distances.path_to(&Pos::new(7, 3)).unwrap(),
+            [Pos::new(7, 1), Pos::new(7, 2), Pos::new(7, 3)]
+        );
+    }
+
+    #[test]
+    fn bfs_path_stops_at_goal() {
+        let path = bfs_path(1, |&n| vec![n + 1, n * 2], |&n| n == 10).unwrap();
+        assert_eq!(path, [1, 2, 4, 5, 10]);
+        assert_eq!(
+            bfs_path(
+                1,
+                |&n| if n < 5 { vec![n + 1] } else { vec![] },
+                |&n| n == 10
+            ),
+            None
+        );
+    }
+
+    // A is joined to D directly at cost 10, or through B and C at 1 + 2 + 3.
+    fn edges(node: &char) -> Vec<(char, u64)> {
+        match node {
+            'A' => vec![('B', 1), ('D', 10)],
+            'B' => vec![('C', 2)],
+            'C' => vec![('D', 3)],
+            _ => vec![],
+        }
+    }
+
+    #[test]
+    fn dijkstra_takes_cheapest_route() {
+        let distances = dijkstra(Some('A'), edges);
+        assert_eq!(distances.get(&'D'), Some(6));
+        assert_eq!(distances.path_to(&'D').unwrap(), ['A', 'B', 'C', 'D']);
+        assert_eq!(
+            dijkstra_path('A', edges, |&n| n == 'D'),
+            Some((vec!['A', 'B', 'C', 'D'], 6))
+        );
+        assert_eq!(dijkstra_path('B', edges, |&n| n == 'A'), None);
+    }
+
+    #[test]
+    fn astar_agrees_with_bfs() {
+        let grid: Grid2D<Cell> = MAZE.parse().unwrap();
+        let (start, goal) = (Pos::new(1, 1), Pos::new(5, 1));
+        let path = grid_path(&grid, start, goal, open).unwrap();
+        let distances = grid_distances(&grid, Some(start), open);
+        assert_eq!(path.len() as u64, distances.get(&goal).unwrap() + 1);
+        assert_eq!((path[0], path[path.len() - 1]), (start, goal));
+        assert!(path.windows(2).all(|w| w[0].manhattan(w[1]) == 1));
+        assert_eq!(grid_path(&grid, start, Pos::new(0, 0), open), None);
+    }
+
+    #[test]
+    fn grid_search_stays_inside_sparse_bounds() {
+        // Open by default, so only the wall and the bounds end the search.
+        let mut grid = Grid::new(Cell::Open);
+        grid.put(Pos::new(0, 0), Cell::Open);
+        grid.put(Pos::new(3, 0), Cell::Open);
+        for y in 0..3 {
+            grid.put(Pos::new(2, y), Cell::Wall);
+        }
+
+        let start = Pos::new(0, 0);
+        let distances = grid_distances(&grid, Some(start), open);
+        assert_eq!(distances.reached().count(), 6);
+        assert_eq!(distances.farthest(), Some((&Pos::new(1, 2), 3)));
+        assert_eq!(
+            grid_path(&grid, start, Pos::new(1, 2), open).unwrap().len(),
+            4
+        );
+        assert_eq!(grid_path(&grid, start, Pos::new(3, 0), open), None);
+        assert_eq!(grid_path(&grid, start, Pos::new(9, 9), open), None);
+    }
+}
@@ -1,6 +1,7 @@
 use super::common::*;
 use super::intcode::IntcodeHandle;
-use std::collections::{HashMap, HashSet};
+use log::debug;
+use std::collections::HashSet;
 use std::sync::mpsc::RecvError;
 
 #[derive(Debug)]
@@ -8,44 +9,50 @@ pub struct Robot {
     grid: Grid<Color>,
     history: HashSet<Pos>,
     r: Pos,
-    dir: Pos,
+    heading: Heading,
     cpu: IntcodeHandle,
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Tile)]
 enum Color {
+    #[tile(' ')]
     Black,
+    #[tile('*')]
     White,
 }
 
 impl Robot {
     pub fn new(cpu: IntcodeHandle) -> Robot {
-        let mut char_map = HashMap::new();
-        char_map.insert(Color::Black, ' ');
-        char_map.insert(Color::White, '*');
-
-        let mut grid = Grid::new(Color::Black, char_map);
+        let mut grid = Grid::new(Color::Black);
         grid.put(Pos::new(0, 0), Color::White);
         Robot {
             grid,
             history: HashSet::new(),
-            r: Pos::new(0, 0),
-            dir: Pos::new(0, 1),
+            r: Pos::ORIGIN,
+            heading: Heading::North,
             cpu,
         }
     }
 
-    pub fn run(&mut self) {
+    /// Runs the robot until its program halts, returning how many tiles it
+    /// painted at least once.
+    pub fn run(&mut self) -> usize {
         loop {
-            match self.read_color() {
-                Color::Black => self.cpu.tx_input.send(0).unwrap(),
-                Color::White => self.cpu.tx_input.send(1).unwrap(),
+            let seen = match self.read_color() {
+                Color::Black => 0,
+                Color::White => 1,
+            };
+            // The program can halt after its last move without asking for
+            // the color under the robot again.
+            if self.cpu.tx_input.send(seen).is_err() {
+                debug!("CPU exited, exiting robot");
+                break;
             }
 
             let (paint, turn) = match self.read_instr() {
                 Ok((paint, turn)) => (paint, turn),
                 Err(_) => {
-                    println!("CPU exited, exiting robot");
+                    debug!("CPU exited, exiting robot");
                     break;
                 }
             };
@@ -65,12 +72,11 @@ impl Robot {
                 x => panic!("Received unknown code from CPU {}", x),
             };
 
-            self.dir.rotate(dir);
-            self.r = self.r + self.dir;
+            self.heading = self.heading.turn(dir);
+            self.r += self.heading;
         }
 
-        println!("Painted {} tiles", self.history.len());
-        self.print_grid();
+        self.history.len()
     }
 
     fn read_instr(&self) -> Result<(i64, i64), RecvError> {
@@ -84,49 +90,7 @@ impl Robot {
         *self.grid.get(&self.r)
     }
 
-    fn print_grid(&self) {
-        self.grid.print_array();
-        //let mut max_x = std::i32::MIN;
-        //let mut min_x = std::i32::MAX;
-        //let mut max_y = std::i32::MIN;
-        //let mut min_y = std::i32::MAX;
-
-        //self.grid.keys().for_each(|pos| {
-            //if pos.x > max_x {
-                //max_x = pos.x;
-            //}
-            //if pos.x < min_x {
-                //min_x = pos.x;
-            //}
-            //if pos.y > max_y {
-                //max_y = pos.y;
-            //}
-            //if pos.y < min_y {
-                //min_y = pos.y
-            //}
-        //});
-
-        //let x_size = max_x + min_x.abs() + 1;
-        //let y_size = max_y + min_y.abs() + 1;
-
-        //let mut array_grid = vec![vec![' '; 1 + x_size as usize]; 1 + y_size as usize];
-
-        //for (pos, color) in &self.grid {
-            //let i = (pos.y + min_y.abs()) as usize;
-            //let j = (pos.x + min_x.abs()) as usize;
-            //let c = match color {
-                //Color::Black => ' ',
-                //Color::White => '*'
-            //};
-
-            //array_grid[i][j] = c;
-        //}
-
-        //for i in (0..y_size).rev() {
-            //for j in 0..x_size {
-                //print!("{}", array_grid[i as usize][j as usize]);
-            //}
-            //println!();
-        //}
+    pub fn print_grid(&self) {
+        self.grid.print_array(Orientation::Y_DOWN);
     }
 }
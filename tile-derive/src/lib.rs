@@ -0,0 +1,159 @@
+//! `#[derive(Tile)]` for enums of map cells. Every variant names the
+//! character it's drawn as, and optionally a color:
+//!
+//! ```ignore
+//! #[derive(Tile)]
+//! enum Cell {
+//!     #[tile('.')]
+//!     Open,
+//!     #[tile('#', color = "blue")]
+//!     Wall,
+//! }
+//! ```
+//!
+//! The impl generated is of `crate::common::Tile`, so the derive only works
+//! inside the crate defining it.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::parse::ParseStream;
+use syn::{
+    parse_macro_input, Data, DeriveInput, Error, Fields, Ident, LitChar, LitStr, Token, Variant,
+};
+
+const COLORS: [(&str, &str); 8] = [
+    ("black", "Black"),
+    ("red", "Red"),
+    ("green", "Green"),
+    ("yellow", "Yellow"),
+    ("blue", "Blue"),
+    ("magenta", "Magenta"),
+    ("cyan", "Cyan"),
+    ("white", "White"),
+];
+
+struct Glyph {
+    variant: Ident,
+    c: LitChar,
+    color: Option<Ident>,
+}
+
+#[proc_macro_derive(Tile, attributes(tile))]
+pub fn derive_tile(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "Tile can only be derived for enums",
+            ))
+        }
+    };
+    let glyphs = data
+        .variants
+        .iter()
+        .map(glyph)
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    for (i, glyph) in glyphs.iter().enumerate() {
+        if let Some(other) = glyphs[..i].iter().find(|g| g.c.value() == glyph.c.value()) {
+            return Err(Error::new_spanned(
+                &glyph.c,
+                format!(
+                    "{:?} is already the tile for {}",
+                    glyph.c.value(),
+                    other.variant
+                ),
+            ));
+        }
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let variants: Vec<_> = glyphs.iter().map(|g| &g.variant).collect();
+    let chars: Vec<_> = glyphs.iter().map(|g| &g.c).collect();
+    let colors = glyphs.iter().map(|g| match &g.color {
+        Some(color) => quote!(Some(crate::common::TermColor::#color)),
+        None => quote!(None),
+    });
+
+    Ok(quote! {
+        impl #impl_generics crate::common::Tile for #name #ty_generics #where_clause {
+            fn to_char(&self) -> char {
+                match self {
+                    #(#name::#variants => #chars,)*
+                }
+            }
+
+            fn from_char(c: char) -> Option<Self> {
+                match c {
+                    #(#chars => Some(#name::#variants),)*
+                    _ => None,
+                }
+            }
+
+            fn color(&self) -> Option<crate::common::TermColor> {
+                match self {
+                    #(#name::#variants => #colors,)*
+                }
+            }
+        }
+    })
+}
+
+/// Reads `#[tile('c')]` or `#[tile('c', color = "name")]` off a variant.
+fn glyph(variant: &Variant) -> syn::Result<Glyph> {
+    if !matches!(variant.fields, Fields::Unit) {
+        return Err(Error::new_spanned(
+            variant,
+            "Tile variants can't have fields",
+        ));
+    }
+    let attr = variant
+        .attrs
+        .iter()
+        .find(|attr| attr.path.is_ident("tile"))
+        .ok_or_else(|| {
+            Error::new_spanned(
+                variant,
+                format!("{} needs a #[tile('c')] attribute", variant.ident),
+            )
+        })?;
+
+    attr.parse_args_with(|input: ParseStream| {
+        let c: LitChar = input.parse()?;
+        let mut color = None;
+        if input.parse::<Option<Token![,]>>()?.is_some() {
+            let key: Ident = input.parse()?;
+            if key != "color" {
+                return Err(Error::new_spanned(key, "Expected `color = \"name\"`"));
+            }
+            input.parse::<Token![=]>()?;
+            let name: LitStr = input.parse()?;
+            let ident = COLORS
+                .iter()
+                .find(|(lower, _)| *lower == name.value())
+                .map(|(_, upper)| Ident::new(upper, name.span()))
+                .ok_or_else(|| {
+                    let known: Vec<_> = COLORS.iter().map(|(lower, _)| *lower).collect();
+                    Error::new_spanned(&name, format!("Color is one of {}", known.join(", ")))
+                })?;
+            color = Some(ident);
+        }
+        Ok(Glyph {
+            variant: variant.ident.clone(),
+            c,
+            color,
+        })
+    })
+}